@@ -1,10 +1,19 @@
-/// Error
-/// 
+//! Error
+//!
 
+use serde::Serialize;
+use std::future::Future;
+use std::sync::RwLock;
 use warp::{
     filters::{body::BodyDeserializeError, cors::CorsForbidden}, 
-    http::StatusCode,
-    reject::Reject,
+    http::{
+        StatusCode,
+        header::{HeaderValue, WWW_AUTHENTICATE},
+    },
+    reject::{
+        InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader,
+        PayloadTooLarge, Reject, UnsupportedMediaType,
+    },
     Rejection, 
     Reply,
 };
@@ -13,7 +22,42 @@ use warp::{
 pub enum Error {
     ParseError(std::num::ParseIntError),
     MissingParameters,
+    InvalidParameter(String),
+    UnknownParameter(String),
+    InvalidCursor,
+    InvalidPayload(String),
+    ValidationFailed(Vec<FieldError>),
+    /// The id in the body, then the one in the path
+    IdMismatch(String, String),
+    UnsupportedMediaType(String),
     QuestionNotFound,
+    QuestionGone,
+    QuestionHasAnswers(usize),
+    QuestionAlreadyExists,
+    /// `If-Match` named a version other than the current one
+    PreconditionFailed,
+    /// Another write got in between reading and updating a record
+    EditConflict,
+    AnswerNotFound,
+    AnswerGone,
+    AnswerAlreadyExists,
+    /// Only deleted questions and answers can be restored
+    NotDeleted,
+    RevisionNotFound,
+    TagNotFound,
+    TagConflict(String),
+    UserNotFound,
+    EmailTaken,
+    ApiKeyNotFound,
+    /// Unknown email or wrong password, deliberately not telling which
+    WrongPassword,
+    /// Missing, malformed, forged or expired bearer token
+    Unauthorized(String),
+    /// Signed in, but not allowed to do this
+    Forbidden(String),
+    ArgonLibraryError,
+    CannotIssueToken,
+    DatabaseQueryError,
 }
 
 impl std::fmt::Display for Error {
@@ -25,37 +69,619 @@ impl std::fmt::Display for Error {
             Error::MissingParameters => {
                 write!(f, "Missing parameter")
             },
+            Error::InvalidParameter(ref reason) => {
+                write!(f, "Invalid parameter: {}", reason)
+            },
+            Error::UnknownParameter(ref name) => {
+                write!(f, "Unknown parameter: {}", name)
+            },
+            Error::InvalidCursor => {
+                write!(f, "Invalid pagination cursor")
+            },
+            Error::InvalidPayload(ref reason) => {
+                write!(f, "Invalid payload: {}", reason)
+            },
+            Error::ValidationFailed(ref errors) => {
+                write!(f, "Validation failed with {} error(s)", errors.len())
+            },
+            Error::IdMismatch(ref body, ref path) => {
+                write!(f, "Id `{}` in the body doesn't match `{}` in the path", body, path)
+            },
+            Error::UnsupportedMediaType(ref reason) => {
+                write!(f, "Unsupported media type: {}", reason)
+            },
             Error::QuestionNotFound => {
                 write!(f, "Question not found")
             },
+            Error::QuestionGone => {
+                write!(f, "Question was deleted")
+            },
+            Error::QuestionHasAnswers(count) => {
+                write!(f, "Question can't be deleted, it has {} answer(s)", count)
+            },
+            Error::QuestionAlreadyExists => {
+                write!(f, "A question with this id already exists")
+            },
+            Error::PreconditionFailed => {
+                write!(f, "The resource was modified since it was fetched")
+            },
+            Error::EditConflict => {
+                write!(f, "The resource was modified concurrently, try again")
+            },
+            Error::AnswerNotFound => {
+                write!(f, "Answer not found")
+            },
+            Error::AnswerGone => {
+                write!(f, "Answer was deleted")
+            },
+            Error::NotDeleted => {
+                write!(f, "Only deleted items can be restored")
+            },
+            Error::AnswerAlreadyExists => {
+                write!(f, "An answer with this id already exists")
+            },
+            Error::RevisionNotFound => {
+                write!(f, "Revision not found")
+            },
+            Error::TagNotFound => {
+                write!(f, "Tag not found")
+            },
+            Error::TagConflict(ref reason) => {
+                write!(f, "Tag conflict: {}", reason)
+            },
+            Error::UserNotFound => {
+                write!(f, "User not found")
+            },
+            Error::ApiKeyNotFound => {
+                write!(f, "API key not found")
+            },
+            Error::EmailTaken => {
+                write!(f, "An account with this email already exists")
+            },
+            Error::WrongPassword => {
+                write!(f, "Wrong email or password")
+            },
+            Error::Unauthorized(ref reason) => {
+                write!(f, "Unauthorized: {}", reason)
+            },
+            Error::Forbidden(ref reason) => {
+                write!(f, "Forbidden: {}", reason)
+            },
+            Error::ArgonLibraryError => {
+                write!(f, "Cannot process the password")
+            },
+            Error::CannotIssueToken => {
+                write!(f, "Cannot issue a token")
+            },
+            Error::DatabaseQueryError => {
+                write!(f, "Cannot query the database")
+            },
+        }
+    }
+}
+
+impl Error {
+    /// HTTP status the error is reported with
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            Error::ParseError(_)
+            | Error::MissingParameters
+            | Error::InvalidParameter(_)
+            | Error::UnknownParameter(_)
+            | Error::InvalidCursor
+            | Error::InvalidPayload(_)
+            | Error::IdMismatch(_, _) => StatusCode::BAD_REQUEST,
+            Error::ValidationFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::QuestionNotFound
+            | Error::AnswerNotFound
+            | Error::RevisionNotFound
+            | Error::TagNotFound
+            | Error::UserNotFound
+            | Error::ApiKeyNotFound => StatusCode::NOT_FOUND,
+            Error::QuestionHasAnswers(_)
+            | Error::QuestionAlreadyExists
+            | Error::AnswerAlreadyExists
+            | Error::EditConflict
+            | Error::NotDeleted
+            | Error::TagConflict(_)
+            | Error::EmailTaken => StatusCode::CONFLICT,
+            Error::QuestionGone | Error::AnswerGone => StatusCode::GONE,
+            Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Error::WrongPassword | Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::ArgonLibraryError | Error::CannotIssueToken | Error::DatabaseQueryError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Stable, machine-readable code clients can match on
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Error::ParseError(_) => "invalid_parameter",
+            Error::MissingParameters => "missing_parameters",
+            Error::InvalidParameter(_) => "invalid_parameter",
+            Error::UnknownParameter(_) => "unknown_parameter",
+            Error::InvalidCursor => "invalid_cursor",
+            Error::InvalidPayload(_) => "invalid_payload",
+            Error::ValidationFailed(_) => "validation_failed",
+            Error::IdMismatch(_, _) => "id_mismatch",
+            Error::UnsupportedMediaType(_) => "unsupported_media_type",
+            Error::QuestionNotFound => "question_not_found",
+            Error::QuestionGone => "question_gone",
+            Error::QuestionHasAnswers(_) => "question_has_answers",
+            Error::QuestionAlreadyExists => "question_already_exists",
+            Error::PreconditionFailed => "precondition_failed",
+            Error::EditConflict => "edit_conflict",
+            Error::AnswerNotFound => "answer_not_found",
+            Error::AnswerGone => "answer_gone",
+            Error::AnswerAlreadyExists => "answer_already_exists",
+            Error::NotDeleted => "not_deleted",
+            Error::RevisionNotFound => "revision_not_found",
+            Error::TagNotFound => "tag_not_found",
+            Error::TagConflict(_) => "tag_conflict",
+            Error::UserNotFound => "user_not_found",
+            Error::EmailTaken => "email_taken",
+            Error::ApiKeyNotFound => "api_key_not_found",
+            Error::WrongPassword => "wrong_password",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::ArgonLibraryError | Error::CannotIssueToken => "internal_error",
+            Error::DatabaseQueryError => "database_error",
         }
     }
 }
 
+/// One broken rule of a payload, listed in the `errors` of the problem
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    /// Path of the field, like `title` or `tags[2]`
+    pub field: String,
+    /// Name of the rule, like `max_length`
+    pub code: &'static str,
+    pub message: String,
+}
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `future`, the handling of one request, with `id` as its request id
+pub async fn with_request_id<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
+
+/// Id of the request being handled, `None` outside of `with_request_id`
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
 // Marker trait. This allows the Error to be returned in a Warp route handler.
 impl Reject for Error {}
 
+/// RFC 7807 problem details, the body of every error response
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// One of the codes from `Error::code`, or the code of a warp rejection
+    pub code: &'static str,
+    /// Also sent as the `X-Request-Id` header and logged with the error
+    pub request_id: String,
+    /// Every field that failed validation, for `validation_failed`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &'static str, detail: String) -> Self {
+        Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail,
+            code,
+            request_id: request_id().unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            errors: Vec::new(),
+        }
+    }
+
+    fn into_reply(self) -> impl Reply {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let request_id = self.request_id.clone();
+        let mut response = warp::reply::with_header(
+            warp::reply::with_header(
+                warp::reply::with_status(warp::reply::json(&self), status),
+                "content-type",
+                "application/problem+json",
+            ),
+            "x-request-id",
+            request_id,
+        )
+        .into_response();
+        // RFC 6750: tell the client which scheme would get it in
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+/// Turns a rejection into a `Problem`, if it is of the registered type
+type Registered = Box<dyn Fn(&Rejection) -> Option<Problem> + Send + Sync>;
+
+static REGISTERED: RwLock<Vec<Registered>> = RwLock::new(Vec::new());
+
+/// Lets applications built on this crate have their own `Reject` types
+/// reported by `return_error`, with the given status and error code and
+/// the type's `Display` output as the detail.
+///
+/// # Example
+/// ```rust
+/// #[derive(Debug)]
+/// struct QuotaExceeded;
+///
+/// impl std::fmt::Display for QuotaExceeded {
+///     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+///         write!(f, "Daily quota exceeded")
+///     }
+/// }
+///
+/// impl warp::reject::Reject for QuotaExceeded {}
+///
+/// handle_errors::register_rejection::<QuotaExceeded>(
+///     warp::http::StatusCode::TOO_MANY_REQUESTS,
+///     "quota_exceeded",
+/// );
+/// ```
+pub fn register_rejection<T>(status: StatusCode, code: &'static str)
+where
+    T: Reject + std::fmt::Display,
+{
+    REGISTERED
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Box::new(move |r: &Rejection| {
+            r.find::<T>()
+                .map(|error| Problem::new(status, code, error.to_string()))
+        }));
+}
+
+fn find_registered(r: &Rejection) -> Option<Problem> {
+    REGISTERED
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find_map(|to_problem| to_problem(r))
+}
+
 pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
-    if let Some(error) = r.find::<Error>() {
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::RANGE_NOT_SATISFIABLE,
-        ))
+    // Rejections from all the routes that were tried end up combined, so
+    // the more specific ones are looked for before `MethodNotAllowed`
+    let problem = if let Some(error) = r.find::<Error>() {
+        let mut problem = Problem::new(error.status(), error.code(), error.to_string());
+        if let Error::ValidationFailed(ref errors) = *error {
+            problem.errors = errors.clone();
+        }
+        problem
     } else if let Some(error) = r.find::<CorsForbidden>() {
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::FORBIDDEN,
-        ))
+        Problem::new(StatusCode::FORBIDDEN, "cors_forbidden", error.to_string())
     } else if let Some(error) = r.find::<BodyDeserializeError>() {
-        Ok(warp::reply::with_status(
+        Problem::new(StatusCode::BAD_REQUEST, "invalid_body", error.to_string())
+    } else if let Some(error) = r.find::<PayloadTooLarge>() {
+        Problem::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", error.to_string())
+    } else if let Some(error) = r.find::<LengthRequired>() {
+        Problem::new(StatusCode::LENGTH_REQUIRED, "length_required", error.to_string())
+    } else if let Some(error) = r.find::<UnsupportedMediaType>() {
+        Problem::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
             error.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ))
-    } 
-    else {
-        Ok(warp::reply::with_status(
-            format!("{:?}", r),
-            StatusCode::NOT_FOUND,
-        ))
+        )
+    } else if let Some(error) = r.find::<InvalidQuery>() {
+        Problem::new(StatusCode::BAD_REQUEST, "invalid_query", error.to_string())
+    } else if let Some(error) = r.find::<MissingHeader>() {
+        Problem::new(StatusCode::BAD_REQUEST, "missing_header", error.to_string())
+    } else if let Some(error) = r.find::<InvalidHeader>() {
+        Problem::new(StatusCode::BAD_REQUEST, "invalid_header", error.to_string())
+    } else if let Some(problem) = find_registered(&r) {
+        problem
+    } else if let Some(error) = r.find::<MethodNotAllowed>() {
+        Problem::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            error.to_string(),
+        )
+    } else {
+        Problem::new(StatusCode::NOT_FOUND, "not_found", "Resource not found".to_string())
+    };
+
+    // The full rejection only goes to the log, never to the client
+    if problem.status >= 500 {
+        log::error!("{} {} {:?}", problem.request_id, problem.code, r);
+    } else {
+        log::warn!("{} {} {:?}", problem.request_id, problem.code, r);
+    }
+
+    Ok(problem.into_reply())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use warp::Filter;
+    use warp::http::HeaderMap;
+
+    /// What `return_error` makes of `rejection`: status, headers and body
+    async fn respond(rejection: Rejection) -> (StatusCode, HeaderMap, Value) {
+        let response = return_error(rejection).await.unwrap().into_response();
+        let (parts, body) = response.into_parts();
+        let body = warp::hyper::body::to_bytes(body).await.unwrap();
+        (parts.status, parts.headers, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// Runs `request` through `filter` and returns how it was rejected
+    macro_rules! rejection {
+        ($request:expr, $filter:expr $(,)?) => {
+            match $request.filter(&$filter).await {
+                Ok(_) => panic!("the request went through"),
+                Err(rejection) => rejection,
+            }
+        };
+    }
+
+    /// Filter that rejects with `error`, once
+    fn failing(error: Error) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+        let error = std::sync::Arc::new(std::sync::Mutex::new(Some(error)));
+        warp::any().and_then(move || {
+            let error = error.lock().unwrap().take().expect("used once");
+            async move { Err::<String, _>(warp::reject::custom(error)) }
+        })
+    }
+
+    #[tokio::test]
+    async fn every_error_has_its_status_and_code() {
+        let parse_error = "x".parse::<usize>().unwrap_err();
+        let cases = [
+            (Error::ParseError(parse_error), 400, "invalid_parameter"),
+            (Error::MissingParameters, 400, "missing_parameters"),
+            (Error::InvalidParameter("p".into()), 400, "invalid_parameter"),
+            (Error::UnknownParameter("p".into()), 400, "unknown_parameter"),
+            (Error::InvalidCursor, 400, "invalid_cursor"),
+            (Error::InvalidPayload("p".into()), 400, "invalid_payload"),
+            (Error::ValidationFailed(Vec::new()), 422, "validation_failed"),
+            (Error::IdMismatch("a".into(), "b".into()), 400, "id_mismatch"),
+            (Error::UnsupportedMediaType("m".into()), 415, "unsupported_media_type"),
+            (Error::QuestionNotFound, 404, "question_not_found"),
+            (Error::QuestionGone, 410, "question_gone"),
+            (Error::QuestionHasAnswers(2), 409, "question_has_answers"),
+            (Error::QuestionAlreadyExists, 409, "question_already_exists"),
+            (Error::PreconditionFailed, 412, "precondition_failed"),
+            (Error::EditConflict, 409, "edit_conflict"),
+            (Error::AnswerNotFound, 404, "answer_not_found"),
+            (Error::AnswerGone, 410, "answer_gone"),
+            (Error::AnswerAlreadyExists, 409, "answer_already_exists"),
+            (Error::NotDeleted, 409, "not_deleted"),
+            (Error::RevisionNotFound, 404, "revision_not_found"),
+            (Error::TagNotFound, 404, "tag_not_found"),
+            (Error::TagConflict("t".into()), 409, "tag_conflict"),
+            (Error::UserNotFound, 404, "user_not_found"),
+            (Error::EmailTaken, 409, "email_taken"),
+            (Error::ApiKeyNotFound, 404, "api_key_not_found"),
+            (Error::WrongPassword, 401, "wrong_password"),
+            (Error::Unauthorized("u".into()), 401, "unauthorized"),
+            (Error::Forbidden("f".into()), 403, "forbidden"),
+            (Error::ArgonLibraryError, 500, "internal_error"),
+            (Error::CannotIssueToken, 500, "internal_error"),
+            (Error::DatabaseQueryError, 500, "database_error"),
+        ];
+        for (error, status, code) in cases {
+            let detail = error.to_string();
+            assert_eq!(error.status().as_u16(), status, "{:?}", error);
+            assert_eq!(error.code(), code, "{:?}", error);
+
+            let (got, headers, body) = respond(warp::reject::custom(error)).await;
+            assert_eq!(got.as_u16(), status, "{}", code);
+            assert_eq!(headers["content-type"], "application/problem+json");
+            assert_eq!(body["type"], "about:blank");
+            assert_eq!(body["status"], status);
+            assert_eq!(body["code"], code);
+            assert_eq!(body["detail"], detail.as_str());
+            assert_eq!(body["title"], got.canonical_reason().unwrap());
+            assert_eq!(headers["x-request-id"], body["request_id"].as_str().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn server_errors_keep_their_cause_out_of_the_detail() {
+        for error in [Error::ArgonLibraryError, Error::DatabaseQueryError] {
+            let (_, _, body) = respond(warp::reject::custom(error)).await;
+            let detail = body["detail"].as_str().unwrap();
+            assert!(detail.starts_with("Cannot "), "{}", detail);
+        }
+    }
+
+    #[tokio::test]
+    async fn unauthorized_names_the_bearer_scheme() {
+        let (_, headers, _) =
+            respond(warp::reject::custom(Error::Unauthorized("no token".into()))).await;
+        assert_eq!(headers[WWW_AUTHENTICATE], "Bearer");
+        let (_, headers, _) = respond(warp::reject::custom(Error::Forbidden("no".into()))).await;
+        assert!(!headers.contains_key(WWW_AUTHENTICATE));
+    }
+
+    #[tokio::test]
+    async fn validation_errors_list_every_field() {
+        let errors = vec![
+            FieldError {
+                field: "title".to_string(),
+                code: "not_blank",
+                message: "must not be blank".to_string(),
+            },
+            FieldError {
+                field: "tags[0]".to_string(),
+                code: "max_length",
+                message: "must be at most 35 characters long".to_string(),
+            },
+        ];
+        let (_, _, body) = respond(warp::reject::custom(Error::ValidationFailed(errors))).await;
+        assert_eq!(body["errors"][0]["field"], "title");
+        assert_eq!(body["errors"][1]["code"], "max_length");
+        // Other problems have no `errors` member at all
+        let (_, _, body) = respond(warp::reject::custom(Error::QuestionNotFound)).await;
+        assert!(body.get("errors").is_none());
+    }
+
+    #[tokio::test]
+    async fn request_id_comes_from_the_request_being_handled() {
+        let rejection = warp::reject::custom(Error::QuestionNotFound);
+        let (_, headers, body) =
+            with_request_id("req-1".to_string(), respond(rejection)).await;
+        assert_eq!(body["request_id"], "req-1");
+        assert_eq!(headers["x-request-id"], "req-1");
+        assert!(request_id().is_none());
+    }
+
+    #[tokio::test]
+    async fn warps_own_rejections() {
+        #[derive(Debug, serde::Deserialize)]
+        #[allow(dead_code)]
+        struct Query {
+            limit: u32,
+        }
+        let cases: Vec<(Rejection, u16, &str)> = vec![
+            (
+                rejection!(warp::test::request().method("POST"), warp::get()),
+                405,
+                "method_not_allowed",
+            ),
+            (
+                rejection!(
+                    warp::test::request().body("not json"),
+                    warp::body::json::<Value>(),
+                ),
+                400,
+                "invalid_body",
+            ),
+            (
+                rejection!(
+                    warp::test::request().body("too long"),
+                    warp::body::content_length_limit(4),
+                ),
+                413,
+                "payload_too_large",
+            ),
+            (
+                rejection!(
+                    warp::test::request().header("content-type", "text/plain").body("{}"),
+                    warp::body::json::<Value>(),
+                ),
+                415,
+                "unsupported_media_type",
+            ),
+            (
+                rejection!(
+                    warp::test::request().path("/?limit=many"),
+                    warp::query::<Query>(),
+                ),
+                400,
+                "invalid_query",
+            ),
+            (
+                rejection!(warp::test::request(), warp::header::<String>("x-needed")),
+                400,
+                "missing_header",
+            ),
+            (
+                rejection!(
+                    warp::test::request().header("x-number", "abc"),
+                    warp::header::<u32>("x-number"),
+                ),
+                400,
+                "invalid_header",
+            ),
+            (
+                rejection!(
+                    warp::test::request()
+                        .method("OPTIONS")
+                        .header("origin", "https://elsewhere.example")
+                        .header("access-control-request-method", "GET"),
+                    warp::any()
+                        .map(warp::reply)
+                        .with(warp::cors().allow_origin("https://here.example")),
+                ),
+                403,
+                "cors_forbidden",
+            ),
+            (warp::reject::not_found(), 404, "not_found"),
+        ];
+        for (rejection, status, code) in cases {
+            let (got, _, body) = respond(rejection).await;
+            assert_eq!((got.as_u16(), body["code"].as_str().unwrap()), (status, code));
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_win_over_method_not_allowed() {
+        // A POST that one route rejects for its method and another for a
+        // missing question: the missing question is what the client needs
+        let routes = warp::get()
+            .map(|| "listing".to_string())
+            .or(warp::post().and(failing(Error::QuestionNotFound)));
+        let rejection = rejection!(warp::test::request().method("POST"), routes);
+        let (status, _, body) = respond(rejection).await;
+        assert_eq!((status.as_u16(), body["code"].as_str().unwrap()), (404, "question_not_found"));
+    }
+
+    #[derive(Debug)]
+    struct QuotaExceeded;
+
+    impl std::fmt::Display for QuotaExceeded {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "Daily quota exceeded")
+        }
+    }
+
+    impl Reject for QuotaExceeded {}
+
+    #[derive(Debug)]
+    struct NeverRegistered;
+
+    impl Reject for NeverRegistered {}
+
+    #[tokio::test]
+    async fn registered_rejections() {
+        register_rejection::<QuotaExceeded>(StatusCode::TOO_MANY_REQUESTS, "quota_exceeded");
+
+        let (status, _, body) = respond(warp::reject::custom(QuotaExceeded)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "quota_exceeded");
+        assert_eq!(body["detail"], "Daily quota exceeded");
+
+        // Registered types are more specific than a method mismatch...
+        let quota = warp::any().and_then(|| async {
+            Err::<String, _>(warp::reject::custom(QuotaExceeded))
+        });
+        let routes = warp::get().map(|| "listing".to_string()).or(warp::post().and(quota));
+        let (_, _, body) = respond(rejection!(warp::test::request().method("POST"), routes)).await;
+        assert_eq!(body["code"], "quota_exceeded");
+
+        // ...but the crate's own errors come first
+        let routes = failing(Error::EditConflict).or(warp::any().and_then(|| async {
+            Err::<String, _>(warp::reject::custom(QuotaExceeded))
+        }));
+        let (_, _, body) = respond(rejection!(warp::test::request(), routes)).await;
+        assert_eq!(body["code"], "edit_conflict");
+
+        // Unregistered types fall through to a plain 404
+        let (status, _, body) = respond(warp::reject::custom(NeverRegistered)).await;
+        assert_eq!((status.as_u16(), body["code"].as_str().unwrap()), (404, "not_found"));
     }
 }
@@ -0,0 +1,121 @@
+use std::env;
+use std::time::Duration;
+
+use crate::types::question::DeletePolicy;
+use crate::types::tag::TagPolicy;
+use crate::types::user::normalize_email;
+
+/// Longest `TOKEN_TTL` accepted, a year
+const MAX_TOKEN_TTL_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Storage backends that can be picked at startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Memory,
+    Sqlite,
+}
+
+/// Runtime configuration, read from environment variables
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// `STORE_BACKEND`: `memory` (default) or `sqlite`
+    pub backend: Backend,
+    /// `DATABASE_PATH`: SQLite database file, used by the `sqlite` backend
+    pub database_path: String,
+    /// `SEED_PATH`: JSON file with the questions a fresh store starts out with
+    pub seed_path: String,
+    /// `SNAPSHOT_DIR`: where the `memory` backend keeps its JSON snapshots.
+    /// Unset means nothing is persisted.
+    pub snapshot_dir: Option<String>,
+    /// `SNAPSHOT_INTERVAL`: seconds between snapshots. Unset means every
+    /// mutation is written through immediately.
+    pub snapshot_interval: Option<Duration>,
+    /// `DELETE_POLICY`: what deleting a question does, `soft` (default)
+    /// moves it to the trash, `refuse` and `cascade` delete it for good
+    pub delete_policy: DeletePolicy,
+    /// `TRASH_RETENTION`: seconds a soft-deleted question or answer stays
+    /// restorable before it is purged, 30 days by default
+    pub trash_retention: Duration,
+    /// `TAG_POLICY`: `open` (default) lets questions use any tag, `strict`
+    /// only the ones in the tag catalog
+    pub tag_policy: TagPolicy,
+    /// `TOKEN_SECRET`: key the login tokens are signed with. Unset means a
+    /// random key, and tokens stop working when the server restarts.
+    pub token_secret: Vec<u8>,
+    /// `TOKEN_TTL`: seconds a login token is valid, 1 day by default and a
+    /// year at most
+    pub token_ttl: Duration,
+    /// `ADMIN_EMAIL`: the already registered account with this email is
+    /// made an admin at startup, which is how the first admin comes to be
+    pub admin_email: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Config, String> {
+        let backend = match env::var("STORE_BACKEND").as_deref() {
+            Err(_) | Ok("memory") => Backend::Memory,
+            Ok("sqlite") => Backend::Sqlite,
+            Ok(other) => return Err(format!("unknown STORE_BACKEND: {}", other)),
+        };
+
+        let snapshot_interval = match env::var("SNAPSHOT_INTERVAL") {
+            Ok(secs) => match secs.parse::<u64>() {
+                Ok(secs) if secs > 0 => Some(Duration::from_secs(secs)),
+                _ => return Err(format!("invalid SNAPSHOT_INTERVAL: {}", secs)),
+            },
+            Err(_) => None,
+        };
+
+        let delete_policy = match env::var("DELETE_POLICY").as_deref() {
+            Err(_) | Ok("soft") => DeletePolicy::Soft,
+            Ok("refuse") => DeletePolicy::Refuse,
+            Ok("cascade") => DeletePolicy::Cascade,
+            Ok(other) => return Err(format!("unknown DELETE_POLICY: {}", other)),
+        };
+
+        let trash_retention = match env::var("TRASH_RETENTION") {
+            Ok(secs) => match secs.parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                _ => return Err(format!("invalid TRASH_RETENTION: {}", secs)),
+            },
+            Err(_) => Duration::from_secs(30 * 24 * 60 * 60),
+        };
+
+        let tag_policy = match env::var("TAG_POLICY").as_deref() {
+            Err(_) | Ok("open") => TagPolicy::Open,
+            Ok("strict") => TagPolicy::Strict,
+            Ok(other) => return Err(format!("unknown TAG_POLICY: {}", other)),
+        };
+
+        let token_secret = match env::var("TOKEN_SECRET") {
+            Ok(secret) if secret.len() >= 32 => secret.into_bytes(),
+            Ok(_) => return Err("TOKEN_SECRET must be at least 32 bytes".to_string()),
+            Err(_) => {
+                log::warn!("TOKEN_SECRET isn't set, login tokens won't survive a restart");
+                rand::random::<[u8; 32]>().to_vec()
+            }
+        };
+
+        let token_ttl = match env::var("TOKEN_TTL") {
+            Ok(secs) => match secs.parse::<u64>() {
+                Ok(secs) if secs > 0 && secs <= MAX_TOKEN_TTL_SECS => Duration::from_secs(secs),
+                _ => return Err(format!("invalid TOKEN_TTL: {}", secs)),
+            },
+            Err(_) => Duration::from_secs(24 * 60 * 60),
+        };
+
+        Ok(Config {
+            backend,
+            database_path: env::var("DATABASE_PATH").unwrap_or_else(|_| "questions.db".to_string()),
+            seed_path: env::var("SEED_PATH").unwrap_or_else(|_| "questions.json".to_string()),
+            snapshot_dir: env::var("SNAPSHOT_DIR").ok(),
+            snapshot_interval,
+            delete_policy,
+            trash_retention,
+            tag_policy,
+            token_secret,
+            token_ttl,
+            admin_email: env::var("ADMIN_EMAIL").ok().map(|email| normalize_email(&email)),
+        })
+    }
+}
@@ -1,20 +1,54 @@
 #![warn(clippy::all)]
+// The route tree is one long `.or` chain, deeper than the default allows
+#![recursion_limit = "256"]
 
 use handle_errors::return_error;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use warp::hyper::server::conn::AddrStream;
+use warp::hyper::service::{make_service_fn, service_fn};
 use warp::{Filter, http::Method};
 
+mod config;
 mod routes;
+mod search;
+mod server;
 mod store;
 mod types;
 
 // These uses shouldn't be required. Find out what's going on...
 use crate::routes::answer::add_answer;
+use crate::routes::answer::delete_answer;
+use crate::routes::answer::get_answers;
+use crate::routes::answer::get_one_answer;
+use crate::routes::answer::restore_answer;
+use crate::routes::answer::update_answer;
+use crate::routes::api_key::{add_api_key, get_api_keys, revoke_api_key};
+use crate::routes::authentication::{Tokens, access, auth, login, optional_auth, register};
+use crate::routes::authorization::{bootstrap_admin, set_role};
+use crate::routes::question::accept_answer;
 use crate::routes::question::add_question;
 use crate::routes::question::delete_question;
 use crate::routes::question::get_one_question;
 use crate::routes::question::get_questions;
+use crate::routes::question::patch_question;
+use crate::routes::question::restore_question;
 use crate::routes::question::update_question;
-use crate::store::Store;
+use crate::routes::revision::{
+    get_answer_revision, get_answer_revisions, get_question_revision, get_question_revisions,
+    rollback_answer, rollback_question,
+};
+use crate::routes::search::search;
+use crate::routes::tag::{delete_tag, get_tag, get_tags, merge_tag, put_tag};
+use crate::routes::trash::get_trash;
+use crate::routes::vote::{vote_answer, vote_question};
+use crate::search::{Index, SearchIndex};
+use crate::types::api_key::Scope;
+use crate::config::Config;
+
+/// Largest request body the JSON and form routes accept, in bytes
+const MAX_BODY_SIZE: u64 = 1024 * 64;
 
 #[tokio::main]
 async fn main() {
@@ -24,88 +58,485 @@ async fn main() {
     log::info!("This is info!");
     log::warn!("This is a warning!");
 
-    let log = warp::log::custom(|info| {
-        log::info!(
-            "{} {} {} {:?} from {} with {:?}",
-            info.method(),
-            info.path(),
-            info.status(),
-            info.elapsed(),
-            info.remote_addr().unwrap(),
-            info.request_headers()
-        );
-    });
+    let config = Config::from_env().expect("Config can't be set");
+    log::info!("Using the {:?} storage backend", config.backend);
 
-    let store = Store::new();
+    let store = store::from_config(&config).expect("Can't open the store");
+    if let Some(email) = &config.admin_email {
+        bootstrap_admin(&store, email)
+            .await
+            .expect("Can't make the ADMIN_EMAIL account an admin");
+    }
+    let shutdown_store = store.clone();
+    let auth_store = store.clone();
+    let index: SearchIndex = Arc::new(RwLock::new(
+        Index::build(&store).await.expect("Can't build the search index"),
+    ));
+    store::purge::spawn(store.clone(), config.trash_retention);
     let store_filter = warp::any().map(move || store.clone());
 
-    let id_filter = warp::any().map(|| uuid::Uuid::new_v4().to_string());
+    let index_filter = warp::any().map(move || index.clone());
+
+    let delete_policy = config.delete_policy;
+    let delete_policy_filter = warp::any().map(move || delete_policy);
+
+    let trash_retention = config.trash_retention;
+    let trash_retention_filter = warp::any().map(move || trash_retention);
+
+    let tag_policy = config.tag_policy;
+    let tag_policy_filter = warp::any().map(move || tag_policy);
+
+    // Set for every request in `server::handle`
+    let id_filter = warp::any().map(|| handle_errors::request_id().unwrap_or_default());
+
+    // Both take the scope an API key needs for the route
+    let tokens = Tokens::new(&config.token_secret, config.token_ttl);
+    let auth_filter = {
+        let (tokens, store) = (tokens.clone(), auth_store.clone());
+        move |scope| auth(tokens.clone(), store.clone(), scope)
+    };
+    let access_filter = {
+        let (tokens, store) = (tokens.clone(), auth_store.clone());
+        move |scope| access(tokens.clone(), store.clone(), scope)
+    };
+    let optional_auth_filter = {
+        let (tokens, store) = (tokens.clone(), auth_store);
+        move |scope| optional_auth(tokens.clone(), store.clone(), scope)
+    };
+    let tokens_filter = warp::any().map(move || tokens.clone());
 
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_header("content-type")
+        .allow_headers([
+            "authorization",
+            "content-type",
+            "if-match",
+            "if-none-match",
+            "x-api-key",
+        ])
+        .expose_headers(["etag", "location"])
         .allow_methods(&[
             Method::PUT, 
+            Method::PATCH,
             Method::DELETE,
             Method::GET,
             Method::POST,
     ]);
 
-    let get_questions = warp::get()
-        .and(warp::path("questions")) // http://localhost:3030/questions
+    // Methods are only checked once the whole path matched, otherwise a
+    // request to an unknown path ends up as a 405 from whichever route
+    // rejected its method first instead of a 404
+    let get_questions = warp::path("questions") // http://localhost:3030/questions
         // .and(warp::path("another"))  // http://localhost:3030/questions/another
         .and(warp::path::end()) // marks the end of the path
+        .and(warp::get())
+        .and(access_filter(Scope::QuestionsRead))
         .and(warp::query()) // this gets the url parameters. Sets first param.
         .and(store_filter.clone()) // Is this a call to a closure? Did it capture the `store` variable? Sets second param.
         .and(id_filter)
         .and_then(get_questions); // get_questions receives 2 params.    
 
-    let get_one_question = warp::get()
-        .and(warp::path("questions"))
+    let get_one_question = warp::path("questions")
         .and(warp::path::param::<String>()) // first param: Id
         .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::QuestionsRead))
+        .and(warp::header::optional::<String>("if-none-match")) // second param: If-None-Match
         .and(store_filter.clone())
         .and_then(get_one_question);
 
-    let add_question = warp::post()
-        .and(warp::path("questions"))
+    let add_question = warp::path("questions")
         .and(warp::path::end())
-        .and(store_filter.clone()) // first param: Store
-        .and(warp::body::json()) // second param: Question
+        .and(warp::post())
+        .and(auth_filter(Scope::QuestionsWrite)) // first param: Session
+        .and(store_filter.clone()) // second param: Store
+        .and(index_filter.clone())
+        .and(tag_policy_filter)
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fifth param: NewQuestion
         .and_then(add_question);
 
-    let update_question = warp::put()
-        .and(warp::path("questions"))
+    let update_question = warp::path("questions")
         .and(warp::path::param::<String>()) // first param: Id
         .and(warp::path::end())
-        .and(store_filter.clone()) // second param: Store
-        .and(warp::body::json()) // third param: Question
+        .and(warp::put())
+        .and(warp::header::optional::<String>("if-match")) // second param: If-Match
+        .and(auth_filter(Scope::QuestionsWrite)) // third param: Session
+        .and(store_filter.clone()) // fourth param: Store
+        .and(index_filter.clone())
+        .and(tag_policy_filter)
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // seventh param: Question
         .and_then(update_question);
 
-    let delete_question = warp::delete()
-        .and(warp::path("questions"))
+    let patch_question = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: Id
+        .and(warp::path::end())
+        .and(warp::patch())
+        .and(warp::header::optional::<String>("if-match")) // second param: If-Match
+        .and(auth_filter(Scope::QuestionsWrite)) // third param: Session
+        .and(store_filter.clone()) // fourth param: Store
+        .and(index_filter.clone())
+        .and(tag_policy_filter)
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(routes::body::merge_patch()) // seventh param: JSON Merge Patch
+        .and_then(patch_question);
+
+    let delete_question = warp::path("questions")
         .and(warp::path::param::<String>()) // first param: id
         .and(warp::path::end())
-        .and(store_filter.clone()) // second param: Store
+        .and(warp::delete())
+        .and(warp::header::optional::<String>("if-match")) // second param: If-Match
+        .and(auth_filter(Scope::QuestionsWrite)) // third param: Session
+        .and(store_filter.clone()) // fourth param: Store
+        .and(index_filter.clone())
+        .and(delete_policy_filter) // sixth param: DeletePolicy
         .and_then(delete_question);
 
-    let add_answer = warp::post()
+    let restore_question = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::QuestionsWrite))
+        .and(store_filter.clone())
+        .and(index_filter.clone())
+        .and_then(restore_question);
+
+    let add_answer = warp::path("answers")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::AnswersWrite)) // first param: Session
+        .and(store_filter.clone()) // second param: Store
+        .and(index_filter.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(routes::body::form()) // fourth param: NewAnswer (url-form-encoded)
+        .and_then(add_answer);
+
+    let get_answers = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: question id
         .and(warp::path("answers"))
         .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::AnswersRead))
+        .and(warp::query()) // second param: pagination
+        .and(store_filter.clone()) // third param: Store
+        .and_then(get_answers);
+
+    let get_one_answer = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::AnswersRead))
+        .and(store_filter.clone())
+        .and_then(get_one_answer);
+
+    let update_answer = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(auth_filter(Scope::AnswersWrite)) // second param: Session
+        .and(store_filter.clone()) // third param: Store
+        .and(index_filter.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fifth param: Answer
+        .and_then(update_answer);
+
+    let delete_answer = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth_filter(Scope::AnswersWrite)) // second param: Session
+        .and(store_filter.clone()) // third param: Store
+        .and(index_filter.clone())
+        .and(delete_policy_filter) // fifth param: DeletePolicy
+        .and_then(delete_answer);
+
+    let restore_answer = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::AnswersWrite))
+        .and(store_filter.clone())
+        .and(index_filter.clone())
+        .and_then(restore_answer);
+
+    let vote_question = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path("votes"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::QuestionsWrite)) // second param: Session
+        .and(store_filter.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fourth param: VoteBody
+        .and_then(vote_question);
+
+    let vote_answer = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: id
+        .and(warp::path("votes"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::AnswersWrite)) // second param: Session
+        .and(store_filter.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fourth param: VoteBody
+        .and_then(vote_answer);
+
+    let accept_answer = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: question id
+        .and(warp::path("accept"))
+        .and(warp::path::param::<String>()) // second param: answer id
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::QuestionsWrite))
+        .and(store_filter.clone())
+        .and_then(accept_answer);
+
+    let get_trash = warp::path("trash")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth_filter(Scope::Admin))
+        .and(warp::query()) // second param: pagination
+        .and(store_filter.clone())
+        .and(trash_retention_filter)
+        .and_then(get_trash);
+
+    let get_question_revisions = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: question id
+        .and(warp::path("revisions"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::QuestionsRead))
+        .and(warp::query()) // second param: order and pagination
+        .and(store_filter.clone())
+        .and_then(get_question_revisions);
+
+    let get_question_revision = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: question id
+        .and(warp::path("revisions"))
+        .and(warp::path::param::<u64>()) // second param: revision number
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::QuestionsRead))
+        .and(store_filter.clone())
+        .and_then(get_question_revision);
+
+    let rollback_question = warp::path("questions")
+        .and(warp::path::param::<String>()) // first param: question id
+        .and(warp::path("revisions"))
+        .and(warp::path::param::<u64>()) // second param: revision number
+        .and(warp::path("rollback"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::header::optional::<String>("if-match")) // third param: If-Match
+        .and(auth_filter(Scope::QuestionsWrite))
+        .and(store_filter.clone())
+        .and(index_filter.clone())
+        .and(tag_policy_filter)
+        .and_then(rollback_question);
+
+    let get_answer_revisions = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: answer id
+        .and(warp::path("revisions"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::AnswersRead))
+        .and(warp::query()) // second param: order and pagination
+        .and(store_filter.clone())
+        .and_then(get_answer_revisions);
+
+    let get_answer_revision = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: answer id
+        .and(warp::path("revisions"))
+        .and(warp::path::param::<u64>()) // second param: revision number
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::AnswersRead))
+        .and(store_filter.clone())
+        .and_then(get_answer_revision);
+
+    let rollback_answer = warp::path("answers")
+        .and(warp::path::param::<String>()) // first param: answer id
+        .and(warp::path("revisions"))
+        .and(warp::path::param::<u64>()) // second param: revision number
+        .and(warp::path("rollback"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::AnswersWrite))
+        .and(store_filter.clone())
+        .and(index_filter.clone())
+        .and_then(rollback_answer);
+
+    let search = warp::path("search")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(optional_auth_filter(Scope::QuestionsRead)) // first param: Option<Session>
+        .and(warp::query()) // second param: q and limit
+        .and(index_filter.clone())
+        .and_then(search);
+
+    let get_tags = warp::path("tags")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::QuestionsRead))
+        .and(warp::query()) // first param: sort, order and pagination
+        .and(store_filter.clone())
+        .and_then(get_tags);
+
+    let get_tag = warp::path("tags")
+        .and(warp::path::param::<String>()) // first param: name
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(access_filter(Scope::QuestionsRead))
+        .and(store_filter.clone())
+        .and_then(get_tag);
+
+    let put_tag = warp::path("tags")
+        .and(warp::path::param::<String>()) // first param: name
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(auth_filter(Scope::TagsWrite)) // second param: Session
+        .and(store_filter.clone()) // third param: Store
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fourth param: TagBody
+        .and_then(put_tag);
+
+    let delete_tag = warp::path("tags")
+        .and(warp::path::param::<String>()) // first param: name
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth_filter(Scope::TagsWrite))
+        .and(store_filter.clone())
+        .and_then(delete_tag);
+
+    let merge_tag = warp::path("tags")
+        .and(warp::path::param::<String>()) // first param: name
+        .and(warp::path("merge"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::TagsWrite)) // second param: Session
+        .and(store_filter.clone()) // third param: Store
+        .and(index_filter)
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fifth param: TagMerge
+        .and_then(merge_tag);
+
+    let registration = warp::path("registration")
+        .and(warp::path::end())
+        .and(warp::post())
         .and(store_filter.clone()) // first param: Store
-        .and(warp::body::form()) // second param: Params (url-form-encoded)
-        .and_then(add_answer);
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // second param: Credentials
+        .and_then(register);
+
+    let set_role = warp::path("users")
+        .and(warp::path::param::<String>()) // first param: user id
+        .and(warp::path("role"))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(auth_filter(Scope::Admin))
+        .and(store_filter.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // fourth param: RoleBody
+        .and_then(set_role);
+
+    let add_api_key = warp::path("api-keys")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(auth_filter(Scope::Admin)) // first param: Session
+        .and(store_filter.clone())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // third param: NewApiKey
+        .and_then(add_api_key);
+
+    let get_api_keys = warp::path("api-keys")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(auth_filter(Scope::Admin))
+        .and(warp::query()) // second param: pagination
+        .and(store_filter.clone())
+        .and_then(get_api_keys);
+
+    let revoke_api_key = warp::path("api-keys")
+        .and(warp::path::param::<String>()) // first param: key id
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(auth_filter(Scope::Admin))
+        .and(store_filter.clone())
+        .and_then(revoke_api_key);
+
+    let login = warp::path("login")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(store_filter.clone()) // first param: Store
+        .and(tokens_filter)
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json()) // third param: Credentials
+        .and_then(login);
 
     let routes = get_questions
         .or(get_one_question)
         .or(add_question)
         .or(update_question)
+        .or(patch_question)
         .or(delete_question)
+        .or(restore_question)
         .or(add_answer)
+        .or(get_answers)
+        .or(get_one_answer)
+        .or(update_answer)
+        .or(delete_answer)
+        .or(restore_answer)
+        .or(vote_question)
+        .or(vote_answer)
+        .or(accept_answer)
+        .or(get_trash)
+        .or(get_question_revisions)
+        .or(get_question_revision)
+        .or(rollback_question)
+        .or(get_answer_revisions)
+        .or(get_answer_revision)
+        .or(rollback_answer)
+        .or(search)
+        .or(get_tags)
+        .or(get_tag)
+        .or(put_tag)
+        .or(delete_tag)
+        .or(merge_tag)
+        .or(registration)
+        .or(login)
+        .or(set_role)
+        .or(add_api_key)
+        .or(get_api_keys)
+        .or(revoke_api_key)
         .with(cors)
-        .with(log)
         .recover(return_error);
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+    // Served through hyper so each request gets its id before warp sees it
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let remote_addr = conn.remote_addr();
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                server::handle(service.clone(), remote_addr, req)
+            }))
+        }
+    });
+    let server = warp::hyper::Server::bind(&([127, 0, 0, 1], 3030).into())
+        .serve(make_service)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        });
+    if let Err(e) = server.await {
+        log::error!("Server error: {}", e);
+    }
+
+    // Don't lose whatever the store hasn't persisted yet
+    if let Err(e) = shutdown_store.flush().await {
+        log::error!("Can't flush the store on shutdown: {}", e);
+    }
 
 }
@@ -1,26 +1,239 @@
-use std::collections::HashMap;
+use chrono::Utc;
 use warp::http::StatusCode;
 
+use crate::routes::authorization::check_owner;
+use crate::routes::question::{created, live_question, unaccept};
+use crate::routes::revision::record_answer;
+use crate::search::SearchIndex;
 use crate::store::Store;
+use crate::types::pagination::paginate;
+use crate::types::query::{QueryParams, extract_answer_query};
+use crate::types::validation::Validate;
 use crate::types::{
-    answer::{Answer, AnswerId},
-    question::QuestionId,
+    answer::{Answer, AnswerId, NewAnswer},
+    question::{DeletePolicy, QuestionId},
+    revision::Revision,
+    user::Session,
 };
+use handle_errors::Error;
+
+pub async fn get_answers(
+    question_id: String,
+    params: QueryParams,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let question_id = QuestionId(question_id);
+    let question = match live_question(&store, &question_id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let query = extract_answer_query(params)?;
+
+    let res: Vec<Answer> = match store.get_answers(&question_id).await {
+        Ok(res) => res.into_iter().filter(|a| a.deleted_at.is_none()).collect(),
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    Ok(warp::reply::json(&paginate(
+        res,
+        &query.pagination,
+        query.order,
+        |a| query.position(a, question.accepted_answer.as_ref()),
+    )))
+}
+
+pub async fn get_one_answer(
+    id: String,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match live_answer(&store, &AnswerId(id)).await {
+        Ok(a) => Ok(warp::reply::json(&a)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
 
 pub async fn add_answer(
+    session: Session,
     store: Store,
-    params: HashMap<String, String>,
+    index: SearchIndex,
+    new_answer: NewAnswer,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = match new_answer.id {
+        Some(id) => id,
+        None => AnswerId(uuid::Uuid::new_v4().to_string()),
+    };
     let answer = Answer {
-        id: AnswerId(params.get("id").unwrap().to_string()),
-        content: params.get("content").unwrap().to_string(),
-        question_id: QuestionId(params.get("questionId").unwrap().to_string()),
+        id,
+        content: new_answer.content,
+        question_id: new_answer.question_id,
+        created_at: Utc::now(),
+        deleted_at: None,
+        author: Some(session.user_id.clone()),
+        score: 0,
+    };
+    answer.validate()?;
+    // Don't store answers to questions that don't exist
+    let question = match live_question(&store, &answer.question_id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let answer = match store.add_answer(answer).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    index.write().await.add_answer(&answer, &question.title);
+    if let Err(e) = record_answer(&store, None, &answer, Some(session.user_id), None).await {
+        return Err(warp::reject::custom(e));
+    }
+    Ok(created(&answer, format!("/answers/{}", answer.id)))
+}
+
+pub async fn update_answer(
+    id: String,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+    answer: Answer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    if answer.id != id {
+        return Err(warp::reject::custom(Error::IdMismatch(answer.id.0, id.0)));
+    }
+    let current = match live_answer(&store, &id).await {
+        Ok(current) => current,
+        Err(e) => return Err(warp::reject::custom(e)),
     };
+    check_owner(&store, &session, current.author.as_ref()).await?;
+    if let Err(e) = replace_answer(&store, &index, &session, current, answer, None).await {
+        return Err(warp::reject::custom(e));
+    }
+
+    Ok(warp::reply::with_status("Answer updated", StatusCode::OK))
+}
+
+/// Validates `answer` and stores it in place of `current`, keeping its
+/// creation time and author, and records the edit as a revision by the
+/// `session`'s user. Answers stay with the question they were posted to.
+pub async fn replace_answer(
+    store: &Store,
+    index: &SearchIndex,
+    session: &Session,
+    current: Answer,
+    mut answer: Answer,
+    rollback_of: Option<u64>,
+) -> Result<Answer, Error> {
+    answer.validate()?;
+    if answer.question_id != current.question_id {
+        return Err(Error::InvalidPayload(
+            "an answer can't be moved to another question".to_string(),
+        ));
+    }
+    answer.created_at = current.created_at;
+    answer.author = current.author.clone();
+    answer.score = current.score;
+    answer.deleted_at = None;
+    let answer = store.update_answer(&current.id, answer).await?;
+    {
+        let mut index = index.write().await;
+        if let Some(title) = index.title_of(&answer.question_id).map(str::to_string) {
+            index.add_answer(&answer, &title);
+        }
+    }
+    record_answer(
+        store,
+        Some(&current),
+        &answer,
+        Some(session.user_id.clone()),
+        rollback_of,
+    )
+    .await?;
+    Ok(answer)
+}
+
+pub async fn delete_answer(
+    id: String,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+    policy: DeletePolicy,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    let mut answer = match live_answer(&store, &id).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, answer.author.as_ref()).await?;
+    if let Err(e) = unaccept(&store, &answer.question_id, &id).await {
+        return Err(warp::reject::custom(e));
+    }
+    // The policy is about what happens to answers of a deleted question,
+    // for a single answer it only decides between trash and gone for good
+    let result = if policy == DeletePolicy::Soft {
+        answer.deleted_at = Some(Utc::now());
+        store.update_answer(&id, answer).await.map(|_| ())
+    } else {
+        purge_answer(&store, &id).await
+    };
+    if let Err(e) = result {
+        return Err(warp::reject::custom(e));
+    }
+    index.write().await.remove_answer(&id);
+    Ok(warp::reply::with_status("Answer deleted", StatusCode::OK))
+}
+
+/// Deletes an answer with its revisions and votes for good
+async fn purge_answer(store: &Store, id: &AnswerId) -> Result<(), Error> {
+    store.delete_answer(id).await?;
+    let subject = Revision::answer_subject(id);
+    store.delete_revisions(&subject).await?;
+    store.delete_votes(&subject).await?;
+    Ok(())
+}
+
+/// Takes an answer out of the trash. Answers deleted along with their
+/// question come back by restoring the question.
+pub async fn restore_answer(
+    id: String,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    let mut answer = match store.get_answer(&id).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, answer.author.as_ref()).await?;
+    if answer.deleted_at.is_none() {
+        return Err(warp::reject::custom(Error::NotDeleted));
+    }
+    let question = match live_question(&store, &answer.question_id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    answer.deleted_at = None;
+    let answer = match store.update_answer(&id, answer).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    index.write().await.add_answer(&answer, &question.title);
+    log::info!("Restored answer {}", answer.id);
+    Ok(warp::reply::json(&answer))
+}
 
-    store
-        .answers
-        .write()
-        .await
-        .insert(answer.id.clone(), answer);
-    Ok(warp::reply::with_status("Answer added", StatusCode::OK))
+/// Fetches an answer, reporting one that was deleted, by itself or along
+/// with its question, as gone
+pub async fn live_answer(store: &Store, id: &AnswerId) -> Result<Answer, Error> {
+    let answer = store.get_answer(id).await?;
+    if answer.deleted_at.is_some() {
+        return Err(Error::AnswerGone);
+    }
+    match live_question(store, &answer.question_id).await {
+        Ok(_) => Ok(answer),
+        Err(Error::QuestionNotFound) => Err(Error::AnswerNotFound),
+        Err(Error::QuestionGone) => Err(Error::AnswerGone),
+        Err(e) => Err(e),
+    }
 }
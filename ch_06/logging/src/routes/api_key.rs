@@ -0,0 +1,122 @@
+use chrono::Utc;
+use warp::http::StatusCode;
+
+use crate::routes::authorization::require_role;
+use crate::store::Store;
+use crate::types::api_key::{self, ApiKey, ApiKeyId, ApiKeyView, CreatedApiKey, NewApiKey};
+use crate::types::pagination::{Cursor, Order, SortKey, extract_pagination, paginate};
+use crate::types::query::{QueryParams, reject_unknown};
+use crate::types::user::{Role, Session};
+use crate::types::validation::{Rule, Validator};
+use handle_errors::Error;
+
+const NAME_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(100)];
+
+/// Creates a key. The response is the only place the key itself shows up,
+/// the store only keeps its hash.
+pub async fn add_api_key(
+    session: Session,
+    store: Store,
+    new_key: NewApiKey,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Admin).await?;
+    Validator::new()
+        .text("name", &new_key.name, NAME_RULES)
+        .finish()?;
+    if new_key.scopes.is_empty() {
+        return Err(warp::reject::custom(Error::InvalidPayload(
+            "field `scopes` must not be empty".to_string(),
+        )));
+    }
+    let owner = new_key.owner.unwrap_or_else(|| session.user_id.clone());
+    match store.get_user(&owner).await {
+        Ok(_) => {}
+        Err(Error::UserNotFound) => {
+            return Err(warp::reject::custom(Error::InvalidPayload(format!(
+                "owner `{}` doesn't exist",
+                owner
+            ))));
+        }
+        Err(e) => return Err(warp::reject::custom(e)),
+    }
+
+    let (key, prefix, hash) = api_key::generate();
+    let mut scopes = Vec::with_capacity(new_key.scopes.len());
+    for scope in new_key.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let api_key = ApiKey {
+        id: ApiKeyId(uuid::Uuid::new_v4().to_string()),
+        name: new_key.name.trim().to_string(),
+        prefix,
+        hash,
+        scopes,
+        owner,
+        created_at: Utc::now(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    match store.add_api_key(api_key).await {
+        Ok(api_key) => {
+            log::info!("User {} created API key {}", session.user_id, api_key.id);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&CreatedApiKey {
+                    key,
+                    api_key: ApiKeyView::from(&api_key),
+                }),
+                StatusCode::CREATED,
+            ))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Every key, revoked ones included, newest first
+pub async fn get_api_keys(
+    session: Session,
+    mut params: QueryParams,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Admin).await?;
+    let pagination = extract_pagination(&mut params)?;
+    reject_unknown(params)?;
+
+    let keys = match store.get_api_keys().await {
+        Ok(keys) => keys,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let views = keys.iter().map(ApiKeyView::from).collect();
+    Ok(warp::reply::json(&paginate(
+        views,
+        &pagination,
+        Order::Desc,
+        |k: &ApiKeyView| Cursor {
+            key: SortKey::Number(k.created_at.timestamp_micros()),
+            id: k.id.0.clone(),
+        },
+    )))
+}
+
+/// Stops a key from working. It stays listed, with when it was revoked.
+pub async fn revoke_api_key(
+    id: String,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Admin).await?;
+    let mut api_key = match store.get_api_key(&ApiKeyId(id)).await {
+        Ok(api_key) => api_key,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    if api_key.revoked_at.is_none() {
+        api_key.revoked_at = Some(Utc::now());
+        api_key = match store.update_api_key(api_key).await {
+            Ok(api_key) => api_key,
+            Err(e) => return Err(warp::reject::custom(e)),
+        };
+        log::info!("User {} revoked API key {}", session.user_id, api_key.id);
+    }
+    Ok(warp::reply::json(&ApiKeyView::from(&api_key)))
+}
@@ -0,0 +1,587 @@
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, errors::ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use warp::Filter;
+use warp::http::StatusCode;
+
+use crate::store::Store;
+use crate::types::api_key::{self, Scope};
+use crate::types::user::{
+    Credentials, Login, Role, Session, User, UserId, UserView, normalize_email,
+};
+use crate::types::validation::Validate;
+use handle_errors::Error;
+
+/// Signs the tokens `POST /login` hands out and checks the ones requests
+/// come with
+#[derive(Clone)]
+pub struct Tokens {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl: Duration,
+}
+
+/// What a token says, signed with HS256
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    /// The user's id
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+impl Tokens {
+    pub fn new(secret: &[u8], ttl: Duration) -> Self {
+        Tokens {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            ttl,
+        }
+    }
+
+    fn issue(&self, user_id: &UserId) -> Result<(String, DateTime<Utc>), Error> {
+        let now = Utc::now();
+        let expires_at = match chrono::Duration::from_std(self.ttl)
+            .ok()
+            .and_then(|ttl| now.checked_add_signed(ttl))
+        {
+            Some(expires_at) => expires_at,
+            None => {
+                log::error!("TOKEN_TTL of {:?} is out of range", self.ttl);
+                return Err(Error::CannotIssueToken);
+            }
+        };
+        let claims = Claims {
+            sub: user_id.0.clone(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+        };
+        match jsonwebtoken::encode(&Header::default(), &claims, &self.encoding) {
+            Ok(token) => Ok((token, expires_at)),
+            Err(e) => {
+                log::error!("Cannot sign token: {}", e);
+                Err(Error::CannotIssueToken)
+            }
+        }
+    }
+
+    fn verify(&self, token: &str) -> Result<Session, Error> {
+        let mut validation = Validation::default();
+        // Expired means expired, clocks are the server's own
+        validation.leeway = 0;
+        match jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation) {
+            Ok(data) => Ok(Session {
+                user_id: UserId(data.claims.sub),
+                scopes: None,
+            }),
+            Err(e) if *e.kind() == ErrorKind::ExpiredSignature => {
+                Err(Error::Unauthorized("token expired".to_string()))
+            }
+            Err(_) => Err(Error::Unauthorized("invalid token".to_string())),
+        }
+    }
+}
+
+/// Lets requests through only with a valid `Authorization: Bearer` token,
+/// or an `X-Api-Key` that has `scope`, handing the route the `Session` it
+/// stands for
+pub fn auth(
+    tokens: Tokens,
+    store: Store,
+    scope: Scope,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    credentials(tokens, store, scope).and_then(|session: Option<Session>| async move {
+        session.ok_or_else(|| {
+            warp::reject::custom(Error::Unauthorized(
+                "missing bearer token or API key".to_string(),
+            ))
+        })
+    })
+}
+
+/// For routes anyone may use. Credentials are optional there, but ones
+/// that are sent still have to be valid, and API keys need `scope`.
+pub fn access(
+    tokens: Tokens,
+    store: Store,
+    scope: Scope,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    credentials(tokens, store, scope)
+        .map(|_: Option<Session>| ())
+        .untuple_one()
+}
+
+/// Like `access`, but hands the `Session` over when there is one, for
+/// routes whose response depends on what the caller may read
+pub fn optional_auth(
+    tokens: Tokens,
+    store: Store,
+    scope: Scope,
+) -> impl Filter<Extract = (Option<Session>,), Error = warp::Rejection> + Clone {
+    credentials(tokens, store, scope)
+}
+
+/// The `Session` of whichever credentials the request has, a bearer token
+/// winning over an API key
+fn credentials(
+    tokens: Tokens,
+    store: Store,
+    scope: Scope,
+) -> impl Filter<Extract = (Option<Session>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and_then(
+            move |authorization: Option<String>, api_key: Option<String>| {
+                let (tokens, store) = (tokens.clone(), store.clone());
+                async move {
+                    let session = match (authorization, api_key) {
+                        (Some(header), _) => Some(bearer(&tokens, &header)?),
+                        (None, Some(key)) => Some(api_key_session(&store, &key, scope).await?),
+                        (None, None) => None,
+                    };
+                    Ok::<_, warp::Rejection>(session)
+                }
+            },
+        )
+}
+
+fn bearer(tokens: &Tokens, header: &str) -> Result<Session, Error> {
+    match header.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            tokens.verify(token.trim())
+        }
+        _ => Err(Error::Unauthorized("expected a bearer token".to_string())),
+    }
+}
+
+/// Keys that are in use only get `last_used_at` written this often, not
+/// on every request
+const LAST_USED_RESOLUTION: chrono::TimeDelta = chrono::TimeDelta::minutes(1);
+
+async fn api_key_session(store: &Store, key: &str, scope: Scope) -> Result<Session, Error> {
+    let mut api_key = match store.get_api_key_by_hash(&api_key::hash(key)).await {
+        Ok(api_key) => api_key,
+        Err(Error::ApiKeyNotFound) => {
+            return Err(Error::Unauthorized("invalid API key".to_string()));
+        }
+        Err(e) => return Err(e),
+    };
+    if api_key.revoked_at.is_some() {
+        return Err(Error::Unauthorized("API key revoked".to_string()));
+    }
+    if !api_key.scopes.contains(&scope) {
+        return Err(Error::Forbidden(format!(
+            "API key lacks the {} scope",
+            scope
+        )));
+    }
+
+    let session = Session {
+        user_id: api_key.owner.clone(),
+        scopes: Some(api_key.scopes.clone()),
+    };
+    let now = Utc::now();
+    if api_key
+        .last_used_at
+        .is_none_or(|at| now - at >= LAST_USED_RESOLUTION)
+    {
+        api_key.last_used_at = Some(now);
+        store.update_api_key(api_key).await?;
+    }
+    Ok(session)
+}
+
+/// Creates an account. The password is only ever stored as an Argon2 hash.
+/// New accounts are plain users.
+pub async fn register(
+    store: Store,
+    credentials: Credentials,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let credentials = Credentials {
+        email: normalize_email(&credentials.email),
+        password: credentials.password,
+    };
+    credentials.validate()?;
+
+    let password = match hash_password(credentials.password).await {
+        Ok(hash) => hash,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let user = User {
+        id: UserId(uuid::Uuid::new_v4().to_string()),
+        email: credentials.email,
+        password,
+        role: Role::User,
+        created_at: Utc::now(),
+    };
+    match store.add_user(user).await {
+        Ok(user) => {
+            log::info!("Registered user {}", user.id);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&UserView::from(&user)),
+                StatusCode::CREATED,
+            ))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Trades an email and password for a bearer token. Unknown emails and
+/// wrong passwords get the same `401`, so the endpoint can't be used to
+/// find out who has an account.
+pub async fn login(
+    store: Store,
+    tokens: Tokens,
+    credentials: Credentials,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = match store
+        .get_user_by_email(&normalize_email(&credentials.email))
+        .await
+    {
+        Ok(user) => user,
+        Err(Error::UserNotFound) => {
+            // Checked anyway so the answer takes as long as for a real account
+            let _ = verify_password(DUMMY_HASH.to_string(), credentials.password).await;
+            return Err(warp::reject::custom(Error::WrongPassword));
+        }
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    match verify_password(user.password.clone(), credentials.password).await {
+        Ok(true) => {}
+        Ok(false) => return Err(warp::reject::custom(Error::WrongPassword)),
+        Err(e) => return Err(warp::reject::custom(e)),
+    }
+    let (token, expires_at) = match tokens.issue(&user.id) {
+        Ok(issued) => issued,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    log::info!("User {} logged in", user.id);
+    Ok(warp::reply::json(&Login {
+        token,
+        token_type: "Bearer",
+        expires_at,
+        user: UserView::from(&user),
+    }))
+}
+
+/// Hash of no one's password, made with the same parameters as real ones,
+/// that `login` checks when there's no account to check against
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$HTCWjGYqXuOuJgH2aPI5Eg$6goJPxpQ+pYWnHDmukN3Cr2PGcnfz1c4/vsgLvz10WU";
+
+/// Hashing is deliberately slow, so it runs on the blocking thread pool
+async fn hash_password(password: String) -> Result<String, Error> {
+    blocking(move || {
+        let salt = SaltString::generate(&mut rand::rngs::OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(argon_error)
+    })
+    .await
+}
+
+async fn verify_password(hash: String, password: String) -> Result<bool, Error> {
+    blocking(move || {
+        let hash = PasswordHash::new(&hash).map_err(argon_error)?;
+        match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(argon_error(e)),
+        }
+    })
+    .await
+}
+
+async fn blocking<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(argon_error)?
+}
+
+/// The reason goes to the log only, clients get a generic `500`
+fn argon_error(e: impl std::fmt::Display) -> Error {
+    log::error!("Argon2 failed: {}", e);
+    Error::ArgonLibraryError
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::types::api_key::{ApiKey, ApiKeyId};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    const SECRET: &[u8] = b"a test secret that is long enough";
+
+    fn tokens() -> Tokens {
+        Tokens::new(SECRET, Duration::from_secs(60))
+    }
+
+    fn store() -> Store {
+        Arc::new(MemoryStore::new(HashMap::new()))
+    }
+
+    fn unauthorized(result: Result<Session, Error>) -> String {
+        match result {
+            Err(Error::Unauthorized(reason)) => reason,
+            other => panic!("expected a 401, got {:?}", other),
+        }
+    }
+
+    /// Stores a key for `owner` with `scopes` and returns its secret
+    async fn api_key(store: &Store, owner: &str, scopes: Vec<Scope>, revoked: bool) -> String {
+        let (key, prefix, hash) = api_key::generate();
+        store
+            .add_api_key(ApiKey {
+                id: ApiKeyId(uuid::Uuid::new_v4().to_string()),
+                name: "test".to_string(),
+                prefix,
+                hash,
+                scopes,
+                owner: UserId(owner.to_string()),
+                created_at: Utc::now(),
+                last_used_at: None,
+                revoked_at: revoked.then(Utc::now),
+            })
+            .await
+            .unwrap();
+        key
+    }
+
+    /// Runs `credentials` over a request with the given headers
+    async fn session_of(
+        store: &Store,
+        headers: &[(&str, &str)],
+        scope: Scope,
+    ) -> Result<Option<Session>, warp::Rejection> {
+        let mut request = warp::test::request();
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request
+            .filter(&credentials(tokens(), store.clone(), scope))
+            .await
+    }
+
+    fn rejected_with(rejection: warp::Rejection) -> Error {
+        match rejection.find::<Error>() {
+            Some(Error::Unauthorized(reason)) => Error::Unauthorized(reason.clone()),
+            Some(Error::Forbidden(reason)) => Error::Forbidden(reason.clone()),
+            other => panic!("unexpected rejection {:?}", other),
+        }
+    }
+
+    #[test]
+    fn issued_tokens_verify_as_unscoped_sessions() {
+        let tokens = tokens();
+        let (token, expires_at) = tokens.issue(&UserId("u1".to_string())).unwrap();
+        let session = tokens.verify(&token).unwrap();
+        assert_eq!(session.user_id, UserId("u1".to_string()));
+        assert!(session.scopes.is_none());
+        assert!(expires_at > Utc::now());
+        assert!(expires_at <= Utc::now() + chrono::Duration::seconds(60));
+    }
+
+    #[test]
+    fn tokens_from_another_secret_or_tampered_are_invalid() {
+        let (token, _) = tokens().issue(&UserId("u1".to_string())).unwrap();
+        let other = Tokens::new(b"another secret, also long enough!", Duration::from_secs(60));
+        assert_eq!(unauthorized(other.verify(&token)), "invalid token");
+
+        let mut tampered = token.clone();
+        tampered.insert(tampered.len() / 2, 'x');
+        assert_eq!(unauthorized(tokens().verify(&tampered)), "invalid token");
+        assert_eq!(unauthorized(tokens().verify("")), "invalid token");
+    }
+
+    #[test]
+    fn tokens_expire_without_leeway() {
+        // A second past `exp` would still pass with jsonwebtoken's default
+        // leeway of a minute
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: "u1".to_string(),
+            iat: now - 120,
+            exp: now - 1,
+        };
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap();
+        assert_eq!(unauthorized(tokens().verify(&token)), "token expired");
+    }
+
+    #[test]
+    fn out_of_range_ttl_cannot_issue() {
+        let tokens = Tokens::new(SECRET, Duration::from_secs(u64::MAX));
+        assert!(matches!(
+            tokens.issue(&UserId("u1".to_string())),
+            Err(Error::CannotIssueToken)
+        ));
+    }
+
+    #[test]
+    fn bearer_header_parsing() {
+        let tokens = tokens();
+        let (token, _) = tokens.issue(&UserId("u1".to_string())).unwrap();
+        for header in [
+            format!("Bearer {}", token),
+            format!("bearer {}", token),
+            format!("BEARER  {} ", token),
+        ] {
+            assert!(bearer(&tokens, &header).is_ok(), "{}", header);
+        }
+        for header in [
+            token.clone(),
+            format!("Basic {}", token),
+            "Bearer".to_string(),
+        ] {
+            assert_eq!(
+                unauthorized(bearer(&tokens, &header)),
+                "expected a bearer token",
+                "{}",
+                header
+            );
+        }
+        assert_eq!(unauthorized(bearer(&tokens, "Bearer ")), "invalid token");
+    }
+
+    #[tokio::test]
+    async fn no_credentials_is_no_session() {
+        let store = store();
+        let session = session_of(&store, &[], Scope::QuestionsRead).await.unwrap();
+        assert!(session.is_none());
+
+        let rejection = warp::test::request()
+            .filter(&auth(tokens(), store, Scope::QuestionsRead))
+            .await
+            .unwrap_err();
+        assert!(matches!(rejected_with(rejection), Error::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn bearer_token_wins_over_an_api_key() {
+        let store = store();
+        let (token, _) = tokens().issue(&UserId("u1".to_string())).unwrap();
+        let bearer = format!("Bearer {}", token);
+        let key = api_key(&store, "u2", vec![Scope::QuestionsRead], false).await;
+
+        let session = session_of(
+            &store,
+            &[("authorization", &bearer), ("x-api-key", &key)],
+            Scope::QuestionsRead,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(session.user_id, UserId("u1".to_string()));
+        assert!(session.scopes.is_none());
+
+        // A bad bearer token isn't rescued by a good key
+        let rejection = session_of(
+            &store,
+            &[("authorization", "Bearer nope"), ("x-api-key", &key)],
+            Scope::QuestionsRead,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(rejected_with(rejection), Error::Unauthorized(_)));
+    }
+
+    #[tokio::test]
+    async fn api_keys_act_as_their_owner_with_their_scopes() {
+        let store = store();
+        let scopes = vec![Scope::QuestionsRead, Scope::AnswersRead];
+        let key = api_key(&store, "u2", scopes.clone(), false).await;
+        let session = session_of(&store, &[("x-api-key", &key)], Scope::AnswersRead)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.user_id, UserId("u2".to_string()));
+        assert_eq!(session.scopes, Some(scopes));
+        assert!(session.has_scope(Scope::QuestionsRead));
+        assert!(!session.has_scope(Scope::Moderate));
+
+        let stored = store.get_api_key_by_hash(&api_key::hash(&key)).await.unwrap();
+        assert!(stored.last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn api_keys_need_the_route_scope() {
+        let store = store();
+        let key = api_key(&store, "u2", vec![Scope::QuestionsRead], false).await;
+        let rejection = session_of(&store, &[("x-api-key", &key)], Scope::QuestionsWrite)
+            .await
+            .unwrap_err();
+        match rejected_with(rejection) {
+            Error::Forbidden(reason) => assert!(reason.contains("questions:write"), "{}", reason),
+            other => panic!("expected a 403, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_and_revoked_api_keys_are_refused() {
+        let store = store();
+        let revoked = api_key(&store, "u2", vec![Scope::QuestionsRead], true).await;
+        for (key, reason) in [
+            (revoked.as_str(), "API key revoked"),
+            ("qak_unknown", "invalid API key"),
+        ] {
+            let rejection = session_of(&store, &[("x-api-key", key)], Scope::QuestionsRead)
+                .await
+                .unwrap_err();
+            match rejected_with(rejection) {
+                Error::Unauthorized(got) => assert_eq!(got, reason),
+                other => panic!("expected a 401, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn dummy_hash_costs_what_a_real_one_does() {
+        let real = hash_password("hunter2".to_string()).await.unwrap();
+        let params = |hash: &str| {
+            let hash = PasswordHash::new(hash).unwrap();
+            (hash.algorithm.to_string(), hash.version, hash.params.to_string())
+        };
+        assert_eq!(params(DUMMY_HASH), params(&real));
+        assert!(!verify_password(DUMMY_HASH.to_string(), String::new()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unknown_email_and_wrong_password_look_alike() {
+        let store = store();
+        let hash = hash_password("right password".to_string()).await.unwrap();
+        store
+            .add_user(User {
+                id: UserId("u1".to_string()),
+                email: "a@example.com".to_string(),
+                password: hash,
+                role: Role::User,
+                created_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+        for email in ["a@example.com", "nobody@example.com"] {
+            let credentials = Credentials {
+                email: email.to_string(),
+                password: "wrong password".to_string(),
+            };
+            let rejection = match login(store.clone(), tokens(), credentials).await {
+                Ok(_) => panic!("{} logged in", email),
+                Err(rejection) => rejection,
+            };
+            assert!(matches!(rejection.find::<Error>(), Some(Error::WrongPassword)));
+        }
+    }
+}
\ No newline at end of file
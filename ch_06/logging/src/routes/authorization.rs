@@ -0,0 +1,252 @@
+use handle_errors::Error;
+
+use crate::store::Store;
+use crate::types::api_key::Scope;
+use crate::types::user::{Role, RoleBody, Session, User, UserId, UserView};
+
+/// Lets the `session`'s user act on something posted by `author`. Authors
+/// may act on their own posts, moderators and admins on everybody's.
+/// Posts from before accounts existed have no author, so only moderators
+/// can touch them. API keys need the `moderate` scope on top of the
+/// owner's role to act on posts that aren't the owner's.
+pub async fn check_owner(
+    store: &Store,
+    session: &Session,
+    author: Option<&UserId>,
+) -> Result<(), Error> {
+    if author == Some(&session.user_id) {
+        return Ok(());
+    }
+    if !session.has_scope(Scope::Moderate) {
+        return Err(Error::Forbidden(format!(
+            "API key lacks the {} scope to act on others' posts",
+            Scope::Moderate
+        )));
+    }
+    match require_role(store, session, Role::Moderator).await {
+        Ok(_) => Ok(()),
+        Err(Error::Forbidden(_)) => Err(Error::Forbidden(
+            "only the author or a moderator can do this".to_string(),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Loads the `session`'s user and checks it has at least `role`. Roles
+/// aren't part of the token, so a changed role counts right away.
+pub async fn require_role(store: &Store, session: &Session, role: Role) -> Result<User, Error> {
+    let user = match store.get_user(&session.user_id).await {
+        Ok(user) => user,
+        // Signed for an account that isn't there anymore
+        Err(Error::UserNotFound) => {
+            return Err(Error::Unauthorized("unknown user".to_string()));
+        }
+        Err(e) => return Err(e),
+    };
+    if user.role < role {
+        return Err(Error::Forbidden(format!("requires the {} role", role)));
+    }
+    Ok(user)
+}
+
+/// Gives an account another role. Admins only, and not for themselves, so
+/// the last admin can't lock everyone out.
+pub async fn set_role(
+    id: String,
+    session: Session,
+    store: Store,
+    body: RoleBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if let Err(e) = require_role(&store, &session, Role::Admin).await {
+        return Err(warp::reject::custom(e));
+    }
+    let id = UserId(id);
+    if id == session.user_id {
+        return Err(warp::reject::custom(Error::Forbidden(
+            "admins can't change their own role".to_string(),
+        )));
+    }
+    let mut user = match store.get_user(&id).await {
+        Ok(user) => user,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    user.role = body.role;
+    match store.update_user(user).await {
+        Ok(user) => {
+            log::info!("User {} made {} a {}", session.user_id, user.id, user.role);
+            Ok(warp::reply::json(&UserView::from(&user)))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Makes the existing account for `email` an admin, which is how the first
+/// admin comes to be. Runs once at startup, so whoever holds `ADMIN_EMAIL`
+/// must have registered before the server was started with it; an account
+/// registered later with that email stays a plain user.
+pub async fn bootstrap_admin(store: &Store, email: &str) -> Result<(), Error> {
+    let mut user = match store.get_user_by_email(email).await {
+        Ok(user) => user,
+        Err(Error::UserNotFound) => {
+            log::warn!("No account for ADMIN_EMAIL, nobody was made an admin");
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+    if user.role != Role::Admin {
+        user.role = Role::Admin;
+        let user = store.update_user(user).await?;
+        log::info!("Made {} an admin through ADMIN_EMAIL", user.id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// A store with one account per role, named after it
+    async fn store() -> Store {
+        let store: Store = Arc::new(MemoryStore::new(HashMap::new()));
+        for (id, role) in [
+            ("user", Role::User),
+            ("moderator", Role::Moderator),
+            ("admin", Role::Admin),
+        ] {
+            store
+                .add_user(User {
+                    id: UserId(id.to_string()),
+                    email: format!("{}@example.com", id),
+                    password: String::new(),
+                    role,
+                    created_at: Utc::now(),
+                })
+                .await
+                .unwrap();
+        }
+        store
+    }
+
+    fn bearer(user: &str) -> Session {
+        Session {
+            user_id: UserId(user.to_string()),
+            scopes: None,
+        }
+    }
+
+    fn api_key(user: &str, scopes: &[Scope]) -> Session {
+        Session {
+            user_id: UserId(user.to_string()),
+            scopes: Some(scopes.to_vec()),
+        }
+    }
+
+    fn author(id: &str) -> UserId {
+        UserId(id.to_string())
+    }
+
+    fn forbidden(result: Result<(), Error>) -> String {
+        match result {
+            Err(Error::Forbidden(reason)) => reason,
+            other => panic!("expected a 403, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn authors_act_on_their_own_posts() {
+        let store = store().await;
+        let own = author("user");
+        assert!(check_owner(&store, &bearer("user"), Some(&own)).await.is_ok());
+        // Even with a key that has no scope for anybody else's
+        let key = api_key("user", &[Scope::QuestionsWrite]);
+        assert!(check_owner(&store, &key, Some(&own)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn users_cant_act_on_others_posts() {
+        let store = store().await;
+        let other = author("someone");
+        let reason = forbidden(check_owner(&store, &bearer("user"), Some(&other)).await);
+        assert_eq!(reason, "only the author or a moderator can do this");
+        // Posts without an author are nobody's own
+        assert!(check_owner(&store, &bearer("user"), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn moderators_and_admins_act_on_everybodys_posts() {
+        let store = store().await;
+        let other = author("someone");
+        for user in ["moderator", "admin"] {
+            assert!(check_owner(&store, &bearer(user), Some(&other)).await.is_ok());
+            assert!(check_owner(&store, &bearer(user), None).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn api_keys_need_the_moderate_scope_for_others_posts() {
+        let store = store().await;
+        let other = author("someone");
+        let without = api_key("admin", &[Scope::QuestionsWrite, Scope::Admin]);
+        let reason = forbidden(check_owner(&store, &without, Some(&other)).await);
+        assert!(reason.contains("moderate"), "{}", reason);
+
+        let with = api_key("moderator", &[Scope::QuestionsWrite, Scope::Moderate]);
+        assert!(check_owner(&store, &with, Some(&other)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn the_moderate_scope_doesnt_replace_the_role() {
+        let store = store().await;
+        let key = api_key("user", &[Scope::Moderate]);
+        let reason = forbidden(check_owner(&store, &key, Some(&author("someone"))).await);
+        assert_eq!(reason, "only the author or a moderator can do this");
+    }
+
+    #[tokio::test]
+    async fn require_role_orders_roles() {
+        let store = store().await;
+        let cases = [
+            ("user", Role::User, true),
+            ("user", Role::Moderator, false),
+            ("moderator", Role::Moderator, true),
+            ("moderator", Role::Admin, false),
+            ("admin", Role::User, true),
+            ("admin", Role::Admin, true),
+        ];
+        for (user, role, allowed) in cases {
+            let result = require_role(&store, &bearer(user), role).await;
+            assert_eq!(result.is_ok(), allowed, "{} as {}", user, role);
+            if !allowed {
+                assert!(matches!(result, Err(Error::Forbidden(_))));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_of_removed_accounts_are_unauthorized() {
+        let store = store().await;
+        assert!(matches!(
+            require_role(&store, &bearer("gone"), Role::User).await,
+            Err(Error::Unauthorized(_))
+        ));
+        assert!(matches!(
+            check_owner(&store, &bearer("gone"), Some(&author("someone"))).await,
+            Err(Error::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn bootstrap_admin_promotes_only_an_existing_account() {
+        let store = store().await;
+        bootstrap_admin(&store, "user@example.com").await.unwrap();
+        let user = store.get_user(&author("user")).await.unwrap();
+        assert_eq!(user.role, Role::Admin);
+
+        assert!(bootstrap_admin(&store, "nobody@example.com").await.is_ok());
+        assert!(store.get_user_by_email("nobody@example.com").await.is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use handle_errors::Error;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use warp::{Filter, hyper::body::Bytes};
+
+/// Like `warp::body::form`, but a body that doesn't fit `T` (a missing
+/// field, a value of the wrong type) is rejected with
+/// `Error::InvalidPayload` naming the offending field.
+pub fn form<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    warp::body::bytes().and_then(|body: Bytes| async move {
+        serde_urlencoded::from_bytes::<T>(&body)
+            .map_err(|e| warp::reject::custom(Error::InvalidPayload(e.to_string())))
+    })
+}
+
+/// Body of a `PATCH`: a JSON Merge Patch document, sent as
+/// `application/merge-patch+json` or plain `application/json`
+pub fn merge_patch() -> impl Filter<Extract = (Value,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(warp::body::bytes())
+        .and_then(|content_type: Option<String>, body: Bytes| async move {
+            let mime = content_type
+                .as_deref()
+                .and_then(|ct| ct.split(';').next())
+                .map(|mime| mime.trim().to_ascii_lowercase());
+            match mime.as_deref() {
+                Some("application/merge-patch+json") | Some("application/json") => {}
+                other => {
+                    return Err(warp::reject::custom(Error::UnsupportedMediaType(format!(
+                        "expected application/merge-patch+json, got {}",
+                        other.unwrap_or("nothing")
+                    ))));
+                }
+            }
+            serde_json::from_slice::<Value>(&body)
+                .map_err(|e| warp::reject::custom(Error::InvalidPayload(e.to_string())))
+        })
+}
@@ -0,0 +1,93 @@
+use handle_errors::Error;
+
+use crate::types::question::Question;
+
+/// Entity tag of what `GET /questions/{id}` returns for `question`. The
+/// score is in it next to the version, since votes change the body without
+/// bumping the version.
+pub fn etag(question: &Question) -> String {
+    format!("\"{}-{}\"", question.version, question.score)
+}
+
+/// Version an entity tag from `etag` was made at. Tags from before the score
+/// was part of them are just the version.
+fn version_of(tag: &str) -> Option<&str> {
+    let tag = tag.strip_prefix('"')?.strip_suffix('"')?;
+    tag.split('-').next()
+}
+
+/// `If-Match`: the write only goes ahead if `question` is still at one of
+/// the listed versions. Only the version part of a tag counts, so a vote in
+/// between doesn't fail an edit. Weak tags never match, as RFC 9110 asks.
+pub fn check_if_match(if_match: Option<&str>, question: &Question) -> Result<(), Error> {
+    let Some(if_match) = if_match else {
+        return Ok(());
+    };
+    let current = question.version.to_string();
+    let matches = if_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || version_of(tag) == Some(current.as_str()));
+    if matches {
+        Ok(())
+    } else {
+        Err(Error::PreconditionFailed)
+    }
+}
+
+/// `If-None-Match`: whether the client already has the current version of
+/// `question` and can be answered with `304 Not Modified`
+pub fn none_match(if_none_match: Option<&str>, question: &Question) -> bool {
+    let Some(if_none_match) = if_none_match else {
+        return false;
+    };
+    let current = etag(question);
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(version: u64, score: i64) -> Question {
+        let mut question: Question = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "title": "t",
+            "content": "c",
+            "tags": null,
+            "version": version,
+        }))
+        .unwrap();
+        question.score = score;
+        question
+    }
+
+    #[test]
+    fn a_vote_changes_the_etag() {
+        let seen = etag(&question(1, 0));
+        assert!(none_match(Some(&seen), &question(1, 0)));
+        assert!(!none_match(Some(&seen), &question(1, 1)));
+        assert!(none_match(Some(&format!("W/{}", seen)), &question(1, 0)));
+        assert!(none_match(Some("*"), &question(1, 1)));
+        assert!(!none_match(None, &question(1, 0)));
+    }
+
+    #[test]
+    fn if_match_compares_versions_only() {
+        let seen = etag(&question(2, 0));
+        assert!(check_if_match(Some(&seen), &question(2, 5)).is_ok());
+        assert!(check_if_match(Some("\"2\""), &question(2, 5)).is_ok());
+        assert!(check_if_match(Some("\"1-0\", \"2-0\""), &question(2, 0)).is_ok());
+        assert!(check_if_match(Some("*"), &question(7, 0)).is_ok());
+        assert!(check_if_match(None, &question(7, 0)).is_ok());
+        assert!(matches!(
+            check_if_match(Some(&seen), &question(3, 0)),
+            Err(Error::PreconditionFailed)
+        ));
+        assert!(check_if_match(Some(&format!("W/{}", seen)), &question(2, 0)).is_err());
+        assert!(check_if_match(Some("2-0"), &question(2, 0)).is_err());
+    }
+}
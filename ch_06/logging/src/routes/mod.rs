@@ -1,2 +1,12 @@
 pub mod answer;
+pub mod api_key;
+pub mod authentication;
+pub mod authorization;
+pub mod body;
+pub mod conditional;
 pub mod question;
+pub mod revision;
+pub mod search;
+pub mod tag;
+pub mod trash;
+pub mod vote;
@@ -1,87 +1,504 @@
-use std::collections::HashMap;
-use warp::http::StatusCode;
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+use warp::{Reply, http::StatusCode};
 
+use crate::routes::authorization::check_owner;
+use crate::routes::conditional::{check_if_match, etag, none_match};
+use crate::routes::revision::record_question;
+use crate::routes::tag::catalog;
+use crate::search::SearchIndex;
 use crate::store::Store;
-use crate::types::pagination::extract_pagination;
-use crate::types::question::{Question, QuestionId};
+use crate::types::pagination::paginate;
+use crate::types::patch::merge;
+use crate::types::query::{QueryParams, extract_question_query};
+use crate::types::answer::{Answer, AnswerId};
+use crate::types::question::{DeletePolicy, DeletedQuestion, NewQuestion, Question, QuestionId};
+use crate::types::revision::Revision;
+use crate::types::tag::TagPolicy;
+use crate::types::user::Session;
+use crate::types::validation::{Validate, Validator};
 use handle_errors::Error;
 
 // Route handler!
 pub async fn get_questions(
-    params: HashMap<String, String>,
+    params: QueryParams,
     store: Store,
     id: String,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     // dbg!("{:#?}", &store);
     log::info!("{} Start querying questions", id);
-    if !params.is_empty() {
-        let mut pagination = extract_pagination(params)?;
-        log::info!("{} Pagination set {:?}", id, &pagination);
-        let res: Vec<Question> = store.questions.read().await.values().cloned().collect();
-        pagination = pagination.saturate(res.len());
-        let res = &res[pagination.start..pagination.end];
-        
-        Ok(warp::reply::json(&res))
-    } else {
-        log::info!("{} No pagination used", id);
-        let res: Vec<Question> = store.questions.read().await.values().cloned().collect();
-        
-        Ok(warp::reply::json(&res))
-    }
+    let mut query = extract_question_query(params)?;
+    log::info!("{} Query set {:?}", id, &query);
+
+    let catalog = match catalog(&store).await {
+        Ok(catalog) => catalog,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    query.tags = query.tags.iter().map(|tag| catalog.resolve(tag)).collect();
+
+    let answer_counts = match store.answer_counts().await {
+        Ok(counts) => counts,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let answers = |q: &Question| answer_counts.get(&q.id).copied().unwrap_or(0);
+    let res: Vec<Question> = match store.get_questions().await {
+        Ok(res) => res
+            .into_iter()
+            .filter(|q| q.deleted_at.is_none())
+            .filter(|q| query.matches(q, answers(q), &catalog))
+            .collect(),
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    // Sorting happens before the page is cut, so pages neither overlap
+    // nor skip questions
+    Ok(warp::reply::json(&paginate(
+        res,
+        &query.pagination,
+        query.order,
+        |q| query.position(q, answers(q)),
+    )))
 }
 
 pub async fn get_one_question(
     id: String,
+    if_none_match: Option<String>,
     store: Store,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.questions.read().await.get(&QuestionId(id)) {
-        Some(q) => Ok(warp::reply::json(&q)),
-        None => Err(warp::reject::custom(Error::QuestionNotFound)),
-    }
+    let question = match live_question(&store, &QuestionId(id)).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let reply = if none_match(if_none_match.as_deref(), &question) {
+        warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED).into_response()
+    } else {
+        warp::reply::json(&question).into_response()
+    };
+    Ok(warp::reply::with_header(reply, "ETag", etag(&question)))
 }
 
 pub async fn add_question(
+    session: Session,
     store: Store,
-    question: Question,
+    index: SearchIndex,
+    tag_policy: TagPolicy,
+    new_question: NewQuestion,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    store
-        .questions
-        .write()
-        .await
-        .insert(question.id.clone(), question);
+    let id = match new_question.id {
+        Some(id) => id,
+        None => QuestionId(uuid::Uuid::new_v4().to_string()),
+    };
+    let catalog = match catalog(&store).await {
+        Ok(catalog) => catalog,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let tags = catalog.apply(new_question.tags);
+    let now = Utc::now();
+    let question = Question {
+        id,
+        title: new_question.title,
+        content: new_question.content,
+        tags,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+        version: 1,
+        author: Some(session.user_id.clone()),
+        accepted_answer: None,
+        score: 0,
+    };
+    let mut validator = Validator::new();
+    question.check(&mut validator);
+    catalog.check(question.tags.as_deref().unwrap_or_default(), tag_policy, &mut validator);
+    validator.finish()?;
+
+    let question = match store.add_question(question).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    index.write().await.add_question(&question);
+    if let Err(e) = record_question(&store, None, &question, Some(session.user_id), None).await {
+        return Err(warp::reject::custom(e));
+    }
 
     // dbg!("{:#?}", store);
 
-    Ok(warp::reply::with_status("Question added!", StatusCode::OK))
+    Ok(warp::reply::with_header(
+        created(&question, format!("/questions/{}", question.id)),
+        "ETag",
+        etag(&question),
+    ))
 }
 
 pub async fn update_question(
     id: String,
+    if_match: Option<String>,
+    session: Session,
     store: Store,
+    index: SearchIndex,
+    tag_policy: TagPolicy,
     question: Question,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.questions.write().await.get_mut(&QuestionId(id)) {
-        Some(q) => *q = question,
-        None => return Err(warp::reject::custom(Error::QuestionNotFound)),
+    let id = QuestionId(id);
+    // The path names the question, the body can't move it elsewhere
+    if question.id != id {
+        return Err(warp::reject::custom(Error::IdMismatch(question.id.0, id.0)));
     }
+    let current = match live_question(&store, &id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, current.author.as_ref()).await?;
+    check_if_match(if_match.as_deref(), &current)?;
+    let replaced = replace_question(&store, &index, tag_policy, &session, current, question, None);
+    let question = match replaced.await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
 
     // dbg!("{:#?}", store);
 
-    Ok(warp::reply::with_status(
-        "Question updated!",
-        StatusCode::OK,
+    Ok(warp::reply::with_header(
+        warp::reply::with_status("Question updated!", StatusCode::OK),
+        "ETag",
+        etag(&question),
     ))
 }
 
+/// Partial update with JSON Merge Patch semantics (RFC 7396)
+/// # Example body
+/// `{"title": "New title", "tags": null}` sets the title, removes the tags
+/// and leaves the content alone
+pub async fn patch_question(
+    id: String,
+    if_match: Option<String>,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+    tag_policy: TagPolicy,
+    patch: Value,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    let Value::Object(fields) = &patch else {
+        return Err(warp::reject::custom(Error::InvalidPayload(
+            "a merge patch must be a JSON object".to_string(),
+        )));
+    };
+    for (field, value) in fields {
+        match field.as_str() {
+            "title" | "content" if value.is_null() => {
+                return Err(warp::reject::custom(Error::InvalidPayload(format!(
+                    "field `{}` can't be removed",
+                    field
+                ))));
+            }
+            "title" | "content" | "tags" => {}
+            "id" if value.as_str() == Some(id.0.as_str()) => {}
+            "id" => {
+                let body_id = match value.as_str() {
+                    Some(body_id) => body_id.to_string(),
+                    None => value.to_string(),
+                };
+                return Err(warp::reject::custom(Error::IdMismatch(body_id, id.0)));
+            }
+            other => {
+                return Err(warp::reject::custom(Error::InvalidPayload(format!(
+                    "field `{}` can't be patched",
+                    other
+                ))));
+            }
+        }
+    }
+
+    let current = match live_question(&store, &id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, current.author.as_ref()).await?;
+    check_if_match(if_match.as_deref(), &current)?;
+    let mut merged = match serde_json::to_value(&current) {
+        Ok(value) => value,
+        Err(_) => return Err(warp::reject::custom(Error::DatabaseQueryError)),
+    };
+    merge(&mut merged, &patch);
+    let question: Question = match serde_json::from_value(merged) {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(Error::InvalidPayload(e.to_string()))),
+    };
+
+    match replace_question(&store, &index, tag_policy, &session, current, question, None).await {
+        Ok(q) => Ok(warp::reply::with_header(
+            warp::reply::json(&q),
+            "ETag",
+            etag(&q),
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Normalizes and validates `question`, then stores it in place of
+/// `current`, keeping the fields the server manages, and records the edit
+/// as a revision by the `session`'s user
+pub async fn replace_question(
+    store: &Store,
+    index: &SearchIndex,
+    tag_policy: TagPolicy,
+    session: &Session,
+    current: Question,
+    mut question: Question,
+    rollback_of: Option<u64>,
+) -> Result<Question, Error> {
+    let catalog = catalog(store).await?;
+    question.tags = catalog.apply(question.tags);
+    let mut validator = Validator::new();
+    question.check(&mut validator);
+    catalog.check(question.tags.as_deref().unwrap_or_default(), tag_policy, &mut validator);
+    validator.finish()?;
+    question.created_at = current.created_at;
+    question.author = current.author.clone();
+    question.accepted_answer = current.accepted_answer.clone();
+    question.score = current.score;
+    question.updated_at = Utc::now();
+    question.deleted_at = None;
+    question.version = current.version + 1;
+    let question = store.update_question(&current.id, question).await?;
+    index.write().await.add_question(&question);
+    record_question(
+        store,
+        Some(&current),
+        &question,
+        Some(session.user_id.clone()),
+        rollback_of,
+    )
+    .await?;
+    Ok(question)
+}
+
 pub async fn delete_question(
     id: String,
+    if_match: Option<String>,
+    session: Session,
     store: Store,
+    index: SearchIndex,
+    policy: DeletePolicy,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    match store.questions.write().await.remove(&QuestionId(id)) {
-        Some(_deleted_question) => {
-            // dbg!(":#?", _deleted_question);
-            Ok(warp::reply::with_status("Question deleted", StatusCode::OK))
+    let id = QuestionId(id);
+    let question = match live_question(&store, &id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, question.author.as_ref()).await?;
+    check_if_match(if_match.as_deref(), &question)?;
+    // Answers already in the trash don't count, they go with the question
+    let answers: Vec<Answer> = match store.get_answers(&id).await {
+        Ok(answers) => answers,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let live: Vec<&Answer> = answers.iter().filter(|a| a.deleted_at.is_none()).collect();
+    let answers_affected = live.len();
+    if policy == DeletePolicy::Refuse && answers_affected > 0 {
+        return Err(warp::reject::custom(Error::QuestionHasAnswers(answers_affected)));
+    }
+
+    let result = match policy {
+        DeletePolicy::Soft => trash_question(&store, question, live).await,
+        DeletePolicy::Refuse | DeletePolicy::Cascade => purge_question(&store, &id, &answers).await,
+    };
+    if let Err(e) = result {
+        return Err(warp::reject::custom(e));
+    }
+    // Whatever the policy, neither the question nor its answers can be
+    // found anymore
+    index.write().await.remove_question(&id);
+
+    log::info!(
+        "Deleted question {} ({:?}), {} answers affected",
+        id,
+        policy,
+        answers_affected
+    );
+    Ok(warp::reply::json(&DeletedQuestion {
+        id,
+        policy,
+        answers_affected,
+    }))
+}
+
+/// Moves `question` and its `answers` to the trash. They all get the same
+/// `deleted_at`, which is how restoring the question finds the answers
+/// that went with it.
+async fn trash_question(
+    store: &Store,
+    mut question: Question,
+    answers: Vec<&Answer>,
+) -> Result<(), Error> {
+    let now = Utc::now();
+    for answer in answers {
+        let mut answer = answer.clone();
+        answer.deleted_at = Some(now);
+        store.update_answer(&answer.id.clone(), answer).await?;
+    }
+    question.deleted_at = Some(now);
+    question.version += 1;
+    store.update_question(&question.id.clone(), question).await?;
+    Ok(())
+}
+
+/// Deletes a question, all of its `answers` and their revisions and votes
+/// for good
+async fn purge_question(store: &Store, id: &QuestionId, answers: &[Answer]) -> Result<(), Error> {
+    store.delete_answers(id).await?;
+    store.delete_question(id).await?;
+    for answer in answers {
+        let subject = Revision::answer_subject(&answer.id);
+        store.delete_revisions(&subject).await?;
+        store.delete_votes(&subject).await?;
+    }
+    let subject = Revision::question_subject(id);
+    store.delete_revisions(&subject).await?;
+    store.delete_votes(&subject).await?;
+    Ok(())
+}
+
+/// Takes a question out of the trash, together with the answers that were
+/// deleted along with it
+pub async fn restore_question(
+    id: String,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    let mut question = match store.get_question(&id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, question.author.as_ref()).await?;
+    let Some(deleted_at) = question.deleted_at else {
+        return Err(warp::reject::custom(Error::NotDeleted));
+    };
+    let answers = match store.get_answers(&id).await {
+        Ok(answers) => answers,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    question.deleted_at = None;
+    question.version += 1;
+    let question = match store.update_question(&id, question).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let mut restored = Vec::new();
+    for mut answer in answers {
+        if answer.deleted_at != Some(deleted_at) {
+            continue;
+        }
+        answer.deleted_at = None;
+        match store.update_answer(&answer.id.clone(), answer).await {
+            Ok(a) => restored.push(a),
+            Err(e) => return Err(warp::reject::custom(e)),
         }
-        None => Err(warp::reject::custom(Error::QuestionNotFound)),
+    }
+
+    {
+        let mut index = index.write().await;
+        index.add_question(&question);
+        for answer in &restored {
+            index.add_answer(answer, &question.title);
+        }
+    }
+    log::info!(
+        "Restored question {} with {} answers",
+        question.id,
+        restored.len()
+    );
+    Ok(warp::reply::with_header(
+        warp::reply::json(&question),
+        "ETag",
+        etag(&question),
+    ))
+}
+
+/// Marks one of the question's answers as the one that solved it, in place
+/// of any accepted before. Only the question's author can.
+pub async fn accept_answer(
+    id: String,
+    answer_id: String,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    let answer_id = AnswerId(answer_id);
+    let mut question = match live_question(&store, &id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    if question.author.as_ref() != Some(&session.user_id) {
+        return Err(warp::reject::custom(Error::Forbidden(
+            "only the question's author can accept an answer".to_string(),
+        )));
+    }
+    let answer = match store.get_answer(&answer_id).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    // An answer to another question isn't one of this question's answers
+    if answer.question_id != id {
+        return Err(warp::reject::custom(Error::AnswerNotFound));
+    }
+    if answer.deleted_at.is_some() {
+        return Err(warp::reject::custom(Error::AnswerGone));
+    }
+
+    if question.accepted_answer.as_ref() != Some(&answer_id) {
+        question.accepted_answer = Some(answer_id);
+        question.version += 1;
+        question = match store.update_question(&id, question).await {
+            Ok(q) => q,
+            Err(e) => return Err(warp::reject::custom(e)),
+        };
+        log::info!("Question {} accepted answer {}", question.id, answer.id);
+    }
+    Ok(warp::reply::with_header(
+        warp::reply::json(&question),
+        "ETag",
+        etag(&question),
+    ))
+}
+
+/// Takes back the acceptance of `answer_id` if `question_id` has it
+/// accepted, for when the answer is deleted
+pub async fn unaccept(
+    store: &Store,
+    question_id: &QuestionId,
+    answer_id: &AnswerId,
+) -> Result<(), Error> {
+    let mut question = store.get_question(question_id).await?;
+    if question.accepted_answer.as_ref() != Some(answer_id) {
+        return Ok(());
+    }
+    question.accepted_answer = None;
+    question.version += 1;
+    store.update_question(question_id, question).await?;
+    Ok(())
+}
+
+/// `201 Created` reply carrying the new entity and its `Location`
+pub fn created<T: Serialize>(entity: &T, location: String) -> warp::reply::Response {
+    warp::reply::with_header(
+        warp::reply::with_status(warp::reply::json(entity), StatusCode::CREATED),
+        "Location",
+        location,
+    )
+    .into_response()
+}
+
+/// Fetches a question, reporting a soft-deleted one as gone
+pub async fn live_question(store: &Store, id: &QuestionId) -> Result<Question, Error> {
+    let question = store.get_question(id).await?;
+    match question.deleted_at {
+        Some(_) => Err(Error::QuestionGone),
+        None => Ok(question),
     }
 }
@@ -0,0 +1,239 @@
+use chrono::{DateTime, Utc};
+
+use crate::routes::answer::{live_answer, replace_answer};
+use crate::routes::authorization::check_owner;
+use crate::routes::conditional::{check_if_match, etag};
+use crate::routes::question::{live_question, replace_question};
+use crate::search::SearchIndex;
+use crate::store::Store;
+use crate::types::pagination::{Cursor, Order, SortKey, extract_pagination, paginate};
+use crate::types::query::{QueryParams, reject_unknown, take_one};
+use crate::types::revision::{Changes, Revision, RevisionView};
+use crate::types::{
+    answer::{Answer, AnswerId},
+    question::{Question, QuestionId},
+    tag::TagPolicy,
+    user::{Session, UserId},
+};
+use handle_errors::Error;
+
+/// Records `question` as it is after an edit. Questions that predate the
+/// revision history first get their `previous` state recorded, so the edit
+/// has something to be compared with.
+pub async fn record_question(
+    store: &Store,
+    previous: Option<&Question>,
+    question: &Question,
+    author: Option<UserId>,
+    rollback_of: Option<u64>,
+) -> Result<Revision, Error> {
+    let previous = previous.map(|p| (Revision::of_question(p, None), p.updated_at));
+    let mut revision = Revision::of_question(question, author);
+    revision.rollback_of = rollback_of;
+    record(store, previous, revision).await
+}
+
+/// Like `record_question`, for answers
+pub async fn record_answer(
+    store: &Store,
+    previous: Option<&Answer>,
+    answer: &Answer,
+    author: Option<UserId>,
+    rollback_of: Option<u64>,
+) -> Result<Revision, Error> {
+    let previous = previous.map(|p| (Revision::of_answer(p, None), p.created_at));
+    let mut revision = Revision::of_answer(answer, author);
+    revision.rollback_of = rollback_of;
+    record(store, previous, revision).await
+}
+
+async fn record(
+    store: &Store,
+    previous: Option<(Revision, DateTime<Utc>)>,
+    mut revision: Revision,
+) -> Result<Revision, Error> {
+    let mut existing = store.get_revisions(&revision.subject).await?;
+    if let Some((mut previous, at)) = previous
+        && existing.is_empty()
+    {
+        previous.created_at = at;
+        previous.changes = Some(Changes::between(None, &previous));
+        existing.push(store.add_revision(previous.numbered(1)).await?);
+    }
+    let last = existing.last();
+    let next = last.map(|r| r.number + 1).unwrap_or(1);
+    revision.changes = Some(Changes::between(last, &revision));
+    store.add_revision(revision.numbered(next)).await
+}
+
+/// Revisions of a question or an answer, newest first unless `order=asc`
+/// # Example query
+/// `/questions/1/revisions?order=asc&limit=10`
+pub async fn get_question_revisions(
+    id: String,
+    params: QueryParams,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    if let Err(e) = live_question(&store, &id).await {
+        return Err(warp::reject::custom(e));
+    }
+    list(&store, &Revision::question_subject(&id), params).await
+}
+
+pub async fn get_question_revision(
+    id: String,
+    number: u64,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    if let Err(e) = live_question(&store, &id).await {
+        return Err(warp::reject::custom(e));
+    }
+    match view(&store, &Revision::question_subject(&id), number).await {
+        Ok(view) => Ok(warp::reply::json(&view)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Restores the title, content and tags of an earlier revision, recorded
+/// as a new revision
+pub async fn rollback_question(
+    id: String,
+    number: u64,
+    if_match: Option<String>,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+    tag_policy: TagPolicy,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    let current = match live_question(&store, &id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, current.author.as_ref()).await?;
+    check_if_match(if_match.as_deref(), &current)?;
+    let revision = match find(&store, &Revision::question_subject(&id), number).await {
+        Ok(revision) => revision,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let mut question = current.clone();
+    question.title = revision.title.unwrap_or_default();
+    question.content = revision.content;
+    question.tags = revision.tags;
+    let rollback = Some(number);
+    match replace_question(&store, &index, tag_policy, &session, current, question, rollback).await {
+        Ok(q) => Ok(warp::reply::with_header(
+            warp::reply::json(&q),
+            "ETag",
+            etag(&q),
+        )),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+pub async fn get_answer_revisions(
+    id: String,
+    params: QueryParams,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    if let Err(e) = live_answer(&store, &id).await {
+        return Err(warp::reject::custom(e));
+    }
+    list(&store, &Revision::answer_subject(&id), params).await
+}
+
+pub async fn get_answer_revision(
+    id: String,
+    number: u64,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    if let Err(e) = live_answer(&store, &id).await {
+        return Err(warp::reject::custom(e));
+    }
+    match view(&store, &Revision::answer_subject(&id), number).await {
+        Ok(view) => Ok(warp::reply::json(&view)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Restores the content of an earlier revision of an answer, recorded as a
+/// new revision
+pub async fn rollback_answer(
+    id: String,
+    number: u64,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    let current = match live_answer(&store, &id).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    check_owner(&store, &session, current.author.as_ref()).await?;
+    let revision = match find(&store, &Revision::answer_subject(&id), number).await {
+        Ok(revision) => revision,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+
+    let mut answer = current.clone();
+    answer.content = revision.content;
+    match replace_answer(&store, &index, &session, current, answer, Some(number)).await {
+        Ok(a) => Ok(warp::reply::json(&a)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+async fn list(
+    store: &Store,
+    subject: &str,
+    mut params: QueryParams,
+) -> Result<warp::reply::Json, warp::Rejection> {
+    let order = Order::parse(take_one(&mut params, "order")?, Order::Desc)?;
+    let pagination = extract_pagination(&mut params)?;
+    reject_unknown(params)?;
+
+    let revisions = match store.get_revisions(subject).await {
+        Ok(revisions) => revisions,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let mut views = Vec::with_capacity(revisions.len());
+    let mut previous: Option<&Revision> = None;
+    for revision in &revisions {
+        views.push(RevisionView::new(revision.clone(), previous));
+        previous = Some(revision);
+    }
+    Ok(warp::reply::json(&paginate(
+        views,
+        &pagination,
+        order,
+        |v| Cursor {
+            key: SortKey::Number(v.revision.number as i64),
+            id: v.revision.id.clone(),
+        },
+    )))
+}
+
+async fn view(store: &Store, subject: &str, number: u64) -> Result<RevisionView, Error> {
+    let revisions = store.get_revisions(subject).await?;
+    let at = revisions
+        .iter()
+        .position(|r| r.number == number)
+        .ok_or(Error::RevisionNotFound)?;
+    let previous = at.checked_sub(1).map(|i| &revisions[i]);
+    Ok(RevisionView::new(revisions[at].clone(), previous))
+}
+
+async fn find(store: &Store, subject: &str, number: u64) -> Result<Revision, Error> {
+    store
+        .get_revisions(subject)
+        .await?
+        .into_iter()
+        .find(|r| r.number == number)
+        .ok_or(Error::RevisionNotFound)
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+
+use crate::search::{Hit, SearchIndex};
+use crate::types::api_key::Scope;
+use crate::types::pagination::{DEFAULT_LIMIT, MAX_LIMIT};
+use crate::types::query::{QueryParams, reject_unknown, take_one};
+use crate::types::user::Session;
+use handle_errors::Error;
+
+/// Body of `GET /search`
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    /// Number of documents that matched, of which `hits` are the best
+    pub total: usize,
+    pub hits: Vec<Hit>,
+}
+
+/// Ranked search over questions and answers. API keys without the
+/// `answers:read` scope only get question hits.
+/// # Example query
+/// `/search?q=borrow+checker&limit=5`
+pub async fn search(
+    session: Option<Session>,
+    mut params: QueryParams,
+    index: SearchIndex,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let query = match take_one(&mut params, "q")? {
+        Some(q) if !q.trim().is_empty() => q,
+        _ => {
+            return Err(warp::reject::custom(Error::InvalidParameter(
+                "q must not be empty".to_string(),
+            )));
+        }
+    };
+    let limit = match take_one(&mut params, "limit")? {
+        Some(limit) => limit.parse::<usize>().map_err(Error::ParseError)?,
+        None => DEFAULT_LIMIT,
+    };
+    if limit == 0 {
+        return Err(warp::reject::custom(Error::InvalidParameter(
+            "limit must be at least 1".to_string(),
+        )));
+    }
+    reject_unknown(params)?;
+
+    let answers = session.is_none_or(|session| session.has_scope(Scope::AnswersRead));
+    let (hits, total) = index
+        .read()
+        .await
+        .search(&query, limit.min(MAX_LIMIT), answers);
+    Ok(warp::reply::json(&SearchResults { query, total, hits }))
+}
@@ -0,0 +1,314 @@
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use std::collections::HashMap;
+use warp::Reply;
+
+use crate::routes::authorization::require_role;
+use crate::routes::question::created;
+use crate::routes::revision::record_question;
+use crate::search::SearchIndex;
+use crate::store::Store;
+use crate::types::pagination::{Cursor, Order, SortKey, extract_pagination, paginate};
+use crate::types::query::{QueryParams, reject_unknown, take_one};
+use crate::types::tag::{
+    MergedTag, TAG_RULES, Tag, TagBody, TagCatalog, TagMerge, TagSummary, normalize,
+};
+use crate::types::user::{Role, Session};
+use crate::types::validation::Validator;
+use handle_errors::Error;
+
+/// Tag name from the path. warp leaves path segments percent-encoded, and
+/// tags like `c#` can't be sent any other way.
+fn path_name(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().into_owned()
+}
+
+/// Loads the tag catalog from the store
+pub async fn catalog(store: &Store) -> Result<TagCatalog, Error> {
+    Ok(TagCatalog::new(store.get_tags().await?))
+}
+
+/// Every tag in the catalog or in use, with the number of live questions
+/// using it
+/// # Example query
+/// `/tags?sort=name&order=asc&limit=50`
+pub async fn get_tags(
+    mut params: QueryParams,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let by_name = match take_one(&mut params, "sort")?.as_deref() {
+        None | Some("count") => false,
+        Some("name") => true,
+        Some(other) => {
+            return Err(warp::reject::custom(Error::InvalidParameter(format!(
+                "sort must be `count` or `name`, not `{}`",
+                other
+            ))));
+        }
+    };
+    let default_order = if by_name { Order::Asc } else { Order::Desc };
+    let order = Order::parse(take_one(&mut params, "order")?, default_order)?;
+    let pagination = extract_pagination(&mut params)?;
+    reject_unknown(params)?;
+
+    let summaries = match summaries(&store).await {
+        Ok(summaries) => summaries,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    Ok(warp::reply::json(&paginate(
+        summaries.into_values().collect(),
+        &pagination,
+        order,
+        |t: &TagSummary| Cursor {
+            key: if by_name {
+                SortKey::Text(t.name.clone())
+            } else {
+                SortKey::Number(t.count as i64)
+            },
+            id: t.name.clone(),
+        },
+    )))
+}
+
+pub async fn get_tag(name: String, store: Store) -> Result<impl warp::Reply, warp::Rejection> {
+    let catalog = match catalog(&store).await {
+        Ok(catalog) => catalog,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let mut summaries = match summaries(&store).await {
+        Ok(summaries) => summaries,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    match summaries.remove(&catalog.resolve(&path_name(&name))) {
+        Some(summary) => Ok(warp::reply::json(&summary)),
+        None => Err(warp::reject::custom(Error::TagNotFound)),
+    }
+}
+
+/// Adds a tag to the catalog or replaces its description and aliases
+pub async fn put_tag(
+    name: String,
+    session: Session,
+    store: Store,
+    body: TagBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Moderator).await?;
+    let name = normalize(&path_name(&name));
+    let aliases: Vec<String> = body.aliases.iter().map(|alias| normalize(alias)).collect();
+    Validator::new()
+        .text("name", &name, TAG_RULES)
+        .list("aliases", &aliases, &[], TAG_RULES)
+        .finish()?;
+    let catalog = match catalog(&store).await {
+        Ok(catalog) => catalog,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    if let Some(other) = catalog.alias_of(&name).filter(|other| *other != name) {
+        return Err(warp::reject::custom(Error::TagConflict(format!(
+            "`{}` is an alias of `{}`",
+            name, other
+        ))));
+    }
+
+    let mut unique: Vec<String> = Vec::new();
+    for alias in aliases {
+        if alias == name || unique.contains(&alias) {
+            continue;
+        }
+        if catalog.get(&alias).is_some() {
+            return Err(warp::reject::custom(Error::TagConflict(format!(
+                "`{}` is a tag of its own, merge it instead",
+                alias
+            ))));
+        }
+        if let Some(other) = catalog.alias_of(&alias).filter(|other| *other != name) {
+            return Err(warp::reject::custom(Error::TagConflict(format!(
+                "`{}` is already an alias of `{}`",
+                alias, other
+            ))));
+        }
+        unique.push(alias);
+    }
+
+    let existed = catalog.get(&name).is_some();
+    let tag = Tag {
+        name,
+        description: body.description.filter(|d| !d.trim().is_empty()),
+        aliases: unique,
+    };
+    match store.put_tag(tag).await {
+        Ok(tag) if existed => Ok(warp::reply::json(&tag).into_response()),
+        Ok(tag) => {
+            let location = format!("/tags/{}", utf8_percent_encode(&tag.name, NON_ALPHANUMERIC));
+            Ok(created(&tag, location))
+        }
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Removes a tag from the catalog. Questions keep using it.
+pub async fn delete_tag(
+    name: String,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Moderator).await?;
+    match store.delete_tag(&normalize(&path_name(&name))).await {
+        Ok(tag) => Ok(warp::reply::json(&tag)),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Retags every question tagged `name` with `into` and makes `name` and its
+/// aliases aliases of `into`. Merging into a tag that isn't in the catalog
+/// yet renames `name`.
+pub async fn merge_tag(
+    name: String,
+    session: Session,
+    store: Store,
+    index: SearchIndex,
+    merge: TagMerge,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Moderator).await?;
+    let from = normalize(&path_name(&name));
+    let catalog = match catalog(&store).await {
+        Ok(catalog) => catalog,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    if let Some(other) = catalog.alias_of(&from) {
+        return Err(warp::reject::custom(Error::TagConflict(format!(
+            "`{}` is an alias of `{}`, merge `{}` instead",
+            from, other, other
+        ))));
+    }
+    let into = catalog.resolve(&merge.into);
+    Validator::new().text("into", &into, TAG_RULES).finish()?;
+    if into == from {
+        return Err(warp::reject::custom(Error::InvalidPayload(
+            "a tag can't be merged into itself".to_string(),
+        )));
+    }
+
+    let questions = match store.get_questions().await {
+        Ok(questions) => questions,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    // Soft-deleted questions are retagged too, so restoring one doesn't
+    // bring the old tag back
+    let tagged: Vec<_> = questions
+        .into_iter()
+        .filter(|q| q.tags.iter().flatten().any(|t| normalize(t) == from))
+        .collect();
+    let source = catalog.get(&from);
+    if source.is_none() && tagged.is_empty() {
+        return Err(warp::reject::custom(Error::TagNotFound));
+    }
+
+    let mut target = catalog.get(&into).cloned().unwrap_or_else(|| Tag {
+        name: into.clone(),
+        description: source.and_then(|s| s.description.clone()),
+        aliases: Vec::new(),
+    });
+    let moved =
+        std::iter::once(from.clone()).chain(source.into_iter().flat_map(|s| s.aliases.clone()));
+    for alias in moved {
+        if alias != target.name && !target.aliases.contains(&alias) {
+            target.aliases.push(alias);
+        }
+    }
+
+    let questions_affected = tagged.len();
+    for previous in tagged {
+        let mut question = previous.clone();
+        let mut tags: Vec<String> = Vec::new();
+        for tag in question.tags.take().into_iter().flatten() {
+            let tag = if normalize(&tag) == from {
+                into.clone()
+            } else {
+                tag
+            };
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        question.tags = Some(tags);
+        question.version += 1;
+        let id = question.id.clone();
+        let question = match store.update_question(&id, question).await {
+            Ok(q) => q,
+            Err(e) => return Err(warp::reject::custom(e)),
+        };
+        if question.deleted_at.is_none() {
+            index.write().await.add_question(&question);
+        }
+        let author = Some(session.user_id.clone());
+        if let Err(e) = record_question(&store, Some(&previous), &question, author, None).await {
+            return Err(warp::reject::custom(e));
+        }
+    }
+
+    let target = match store.put_tag(target).await {
+        Ok(tag) => tag,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    if source.is_some()
+        && let Err(e) = store.delete_tag(&from).await
+    {
+        return Err(warp::reject::custom(e));
+    }
+
+    log::info!(
+        "Merged tag {} into {}, {} questions retagged",
+        from,
+        target.name,
+        questions_affected
+    );
+    Ok(warp::reply::json(&MergedTag {
+        from,
+        into: target,
+        questions_affected,
+    }))
+}
+
+/// Summaries of the catalog and of every tag used by a live question,
+/// keyed by name. Tags in use are counted under the tag they resolve to.
+async fn summaries(store: &Store) -> Result<HashMap<String, TagSummary>, Error> {
+    let catalog = catalog(store).await?;
+    let mut summaries: HashMap<String, TagSummary> = catalog
+        .tags()
+        .map(|tag| {
+            let summary = TagSummary {
+                name: tag.name.clone(),
+                description: tag.description.clone(),
+                aliases: tag.aliases.clone(),
+                count: 0,
+                known: true,
+            };
+            (tag.name.clone(), summary)
+        })
+        .collect();
+
+    for question in store.get_questions().await? {
+        if question.deleted_at.is_some() {
+            continue;
+        }
+        let mut seen: Vec<String> = Vec::new();
+        for tag in question.tags.iter().flatten() {
+            let name = catalog.resolve(tag);
+            if name.is_empty() || seen.contains(&name) {
+                continue;
+            }
+            summaries
+                .entry(name.clone())
+                .or_insert_with(|| TagSummary {
+                    name: name.clone(),
+                    description: None,
+                    aliases: Vec::new(),
+                    count: 0,
+                    known: false,
+                })
+                .count += 1;
+            seen.push(name);
+        }
+    }
+    Ok(summaries)
+}
@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+use crate::routes::authorization::require_role;
+use crate::store::Store;
+use crate::types::pagination::{Cursor, Order, SortKey, extract_pagination, paginate};
+use crate::types::query::{QueryParams, reject_unknown};
+use crate::types::trash::{TrashItem, TrashKind};
+use crate::types::user::{Role, Session};
+
+/// Soft-deleted questions and answers, most recently deleted first. Admins
+/// only, the trash holds everybody's deleted posts.
+/// # Example query
+/// `/trash?limit=20`
+pub async fn get_trash(
+    session: Session,
+    mut params: QueryParams,
+    store: Store,
+    retention: Duration,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_role(&store, &session, Role::Admin).await?;
+    let pagination = extract_pagination(&mut params)?;
+    reject_unknown(params)?;
+
+    let questions = match store.get_questions().await {
+        Ok(questions) => questions,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let retention = chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::MAX);
+    let purge_at = |deleted_at: DateTime<Utc>| {
+        deleted_at
+            .checked_add_signed(retention)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    };
+
+    let mut items = Vec::new();
+    for question in questions {
+        let answers = match store.get_answers(&question.id).await {
+            Ok(answers) => answers,
+            Err(e) => return Err(warp::reject::custom(e)),
+        };
+        for answer in answers {
+            let Some(deleted_at) = answer.deleted_at else {
+                continue;
+            };
+            // Deleted along with the question
+            if question.deleted_at == Some(deleted_at) {
+                continue;
+            }
+            items.push(TrashItem {
+                kind: TrashKind::Answer,
+                id: answer.id.0,
+                question_id: question.id.0.clone(),
+                title: question.title.clone(),
+                deleted_at,
+                purge_at: purge_at(deleted_at),
+            });
+        }
+        if let Some(deleted_at) = question.deleted_at {
+            items.push(TrashItem {
+                kind: TrashKind::Question,
+                id: question.id.0.clone(),
+                question_id: question.id.0,
+                title: question.title,
+                deleted_at,
+                purge_at: purge_at(deleted_at),
+            });
+        }
+    }
+
+    Ok(warp::reply::json(&paginate(
+        items,
+        &pagination,
+        Order::Desc,
+        |item| Cursor {
+            key: SortKey::Number(item.deleted_at.timestamp_micros()),
+            id: format!("{}/{}", item.question_id, item.id),
+        },
+    )))
+}
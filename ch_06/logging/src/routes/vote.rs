@@ -0,0 +1,88 @@
+use handle_errors::Error;
+
+use crate::routes::answer::live_answer;
+use crate::routes::question::live_question;
+use crate::store::Store;
+use crate::types::answer::AnswerId;
+use crate::types::question::QuestionId;
+use crate::types::revision::Revision;
+use crate::types::user::{Session, UserId};
+use crate::types::vote::{Direction, Vote, VoteBody, VoteResult};
+
+/// Casts, changes or takes back the caller's vote on a question
+pub async fn vote_question(
+    id: String,
+    session: Session,
+    store: Store,
+    body: VoteBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = QuestionId(id);
+    let question = match live_question(&store, &id).await {
+        Ok(q) => q,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let subject = Revision::question_subject(&id);
+    if let Err(e) = cast(&store, &session, subject, question.author.as_ref(), body.vote).await {
+        return Err(warp::reject::custom(e));
+    }
+    match store.get_question(&id).await {
+        Ok(q) => Ok(warp::reply::json(&VoteResult {
+            score: q.score,
+            vote: body.vote,
+        })),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Like `vote_question`, for answers
+pub async fn vote_answer(
+    id: String,
+    session: Session,
+    store: Store,
+    body: VoteBody,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let id = AnswerId(id);
+    let answer = match live_answer(&store, &id).await {
+        Ok(a) => a,
+        Err(e) => return Err(warp::reject::custom(e)),
+    };
+    let subject = Revision::answer_subject(&id);
+    if let Err(e) = cast(&store, &session, subject, answer.author.as_ref(), body.vote).await {
+        return Err(warp::reject::custom(e));
+    }
+    match store.get_answer(&id).await {
+        Ok(a) => Ok(warp::reply::json(&VoteResult {
+            score: a.score,
+            vote: body.vote,
+        })),
+        Err(e) => Err(warp::reject::custom(e)),
+    }
+}
+
+/// Replaces whatever vote the `session`'s user had on `subject` with
+/// `vote`. Nobody votes on their own posts.
+async fn cast(
+    store: &Store,
+    session: &Session,
+    subject: String,
+    author: Option<&UserId>,
+    vote: Option<Direction>,
+) -> Result<(), Error> {
+    if author == Some(&session.user_id) {
+        return Err(Error::Forbidden(
+            "you can't vote on what you posted".to_string(),
+        ));
+    }
+    match vote {
+        Some(direction) => {
+            let vote = Vote::new(subject, session.user_id.clone(), direction);
+            store.put_vote(vote).await?;
+        }
+        None => {
+            store
+                .delete_vote(&Vote::id_of(&subject, &session.user_id))
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,561 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+use crate::types::{
+    answer::{Answer, AnswerId},
+    question::{Question, QuestionId},
+};
+
+/// Shared handle to the search index, kept up to date by the route handlers
+pub type SearchIndex = Arc<RwLock<Index>>;
+
+/// BM25 term frequency saturation
+const K1: f64 = 1.2;
+/// BM25 document length normalization
+const B: f64 = 0.75;
+/// Roughly how many characters of context a snippet shows
+const SNIPPET_LEN: usize = 160;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "can", "do", "does", "for", "from",
+    "has", "have", "how", "i", "if", "in", "into", "is", "it", "its", "me", "my", "no", "not",
+    "of", "on", "or", "so", "such", "that", "the", "their", "then", "there", "these", "they",
+    "this", "to", "was", "we", "what", "when", "where", "which", "who", "why", "will", "with",
+    "you", "your",
+];
+
+/// A question or an answer, the two kinds of documents in the index
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DocKey {
+    Question(QuestionId),
+    Answer(AnswerId),
+}
+
+#[derive(Debug)]
+struct Doc {
+    /// The question the document is, or belongs to
+    question_id: QuestionId,
+    /// Title of that question, shown with every hit
+    title: String,
+    /// Text the snippet is cut from
+    body: String,
+    /// Number of terms, for BM25's length normalization
+    length: usize,
+    /// Distinct terms, so removing the document finds its postings
+    terms: HashSet<String>,
+}
+
+/// In-process inverted index over questions and answers
+#[derive(Debug, Default)]
+pub struct Index {
+    /// term -> document -> how often the term occurs in it
+    postings: HashMap<String, HashMap<DocKey, usize>>,
+    docs: HashMap<DocKey, Doc>,
+    /// Sum of all document lengths, for the average
+    total_length: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HitKind {
+    Question,
+    Answer,
+}
+
+/// One ranked search result
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub kind: HitKind,
+    pub question_id: QuestionId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer_id: Option<AnswerId>,
+    pub title: String,
+    pub score: f64,
+    /// Excerpt around the first match, HTML-escaped, with the matching
+    /// words wrapped in `<mark>`
+    pub snippet: String,
+}
+
+impl Index {
+    /// Builds the index from everything live in the store
+    pub async fn build(store: &Store) -> Result<Index, handle_errors::Error> {
+        let mut index = Index::default();
+        for question in store.get_questions().await? {
+            if question.deleted_at.is_some() {
+                continue;
+            }
+            for answer in store.get_answers(&question.id).await? {
+                if answer.deleted_at.is_some() {
+                    continue;
+                }
+                index.add_answer(&answer, &question.title);
+            }
+            index.add_question(&question);
+        }
+        log::info!("Indexed {} documents", index.docs.len());
+        Ok(index)
+    }
+
+    /// Adds `question`, or re-indexes it if it is already there. The
+    /// answers keep pointing at the question but show its new title.
+    pub fn add_question(&mut self, question: &Question) {
+        let mut text = format!("{}\n{}", question.title, question.content);
+        for tag in question.tags.iter().flatten() {
+            text.push('\n');
+            text.push_str(tag);
+        }
+        for doc in self.docs.values_mut() {
+            if doc.question_id == question.id {
+                doc.title = question.title.clone();
+            }
+        }
+        self.insert(
+            DocKey::Question(question.id.clone()),
+            question.id.clone(),
+            question.title.clone(),
+            question.content.clone(),
+            &text,
+        );
+    }
+
+    /// Drops `id` and all of its answers from the index
+    pub fn remove_question(&mut self, id: &QuestionId) {
+        let keys: Vec<DocKey> = self
+            .docs
+            .iter()
+            .filter(|(_, doc)| &doc.question_id == id)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    /// Adds `answer` to the question titled `title`, or re-indexes it
+    pub fn add_answer(&mut self, answer: &Answer, title: &str) {
+        self.insert(
+            DocKey::Answer(answer.id.clone()),
+            answer.question_id.clone(),
+            title.to_string(),
+            answer.content.clone(),
+            &answer.content,
+        );
+    }
+
+    pub fn remove_answer(&mut self, id: &AnswerId) {
+        self.remove(&DocKey::Answer(id.clone()));
+    }
+
+    /// Title of an indexed question, so new answers can be indexed with it
+    pub fn title_of(&self, id: &QuestionId) -> Option<&str> {
+        self.docs
+            .get(&DocKey::Question(id.clone()))
+            .map(|doc| doc.title.as_str())
+    }
+
+    /// The best `limit` hits for `query` by BM25, and how many documents
+    /// matched in total. Answers are left out unless `answers` is set.
+    pub fn search(&self, query: &str, limit: usize, answers: bool) -> (Vec<Hit>, usize) {
+        let terms: HashSet<String> = tokenize(query).collect();
+        if terms.is_empty() || self.docs.is_empty() {
+            return (Vec::new(), 0);
+        }
+
+        let docs = self.docs.len() as f64;
+        let average_length = self.total_length as f64 / docs;
+        let mut scores: HashMap<&DocKey, f64> = HashMap::new();
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else {
+                continue;
+            };
+            let df = postings.len() as f64;
+            let idf = ((docs - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (key, &tf) in postings {
+                if !answers && matches!(key, DocKey::Answer(_)) {
+                    continue;
+                }
+                let tf = tf as f64;
+                let length = self.docs[key].length as f64;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(key).or_insert(0.0) += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let total = scores.len();
+        let mut ranked: Vec<(&DocKey, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let hits = ranked
+            .into_iter()
+            .take(limit)
+            .map(|(key, score)| {
+                let doc = &self.docs[key];
+                let (kind, answer_id) = match key {
+                    DocKey::Question(_) => (HitKind::Question, None),
+                    DocKey::Answer(id) => (HitKind::Answer, Some(id.clone())),
+                };
+                Hit {
+                    kind,
+                    question_id: doc.question_id.clone(),
+                    answer_id,
+                    title: doc.title.clone(),
+                    score,
+                    snippet: snippet(&doc.body, &terms),
+                }
+            })
+            .collect();
+        (hits, total)
+    }
+
+    fn insert(
+        &mut self,
+        key: DocKey,
+        question_id: QuestionId,
+        title: String,
+        body: String,
+        text: &str,
+    ) {
+        self.remove(&key);
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut length = 0;
+        for term in tokenize(text) {
+            *counts.entry(term).or_insert(0) += 1;
+            length += 1;
+        }
+        for (term, count) in &counts {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(key.clone(), *count);
+        }
+        self.total_length += length;
+        self.docs.insert(
+            key,
+            Doc {
+                question_id,
+                title,
+                body,
+                length,
+                terms: counts.into_keys().collect(),
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &DocKey) {
+        let Some(doc) = self.docs.remove(key) else {
+            return;
+        };
+        self.total_length -= doc.length;
+        for term in &doc.terms {
+            if let Some(postings) = self.postings.get_mut(term) {
+                postings.remove(key);
+                if postings.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+}
+
+/// Splits `text` into words, each with its byte offset
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+/// Index terms of `text`: lowercased, stemmed words without stop words
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    words(text).filter_map(|(_, word)| term(word))
+}
+
+fn term(word: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    if STOP_WORDS.contains(&word.as_str()) {
+        return None;
+    }
+    Some(stem(&word))
+}
+
+/// Light suffix-stripping stemmer for English, so that "borrowing",
+/// "borrowed" and "borrows" all end up as "borrow". Short words are left
+/// alone, since stripping them mostly merges unrelated words.
+fn stem(word: &str) -> String {
+    if word.chars().count() <= 3 || !word.is_ascii() {
+        return word.to_string();
+    }
+    const SUFFIXES: &[(&str, &str)] = &[
+        ("ational", "ate"),
+        ("ization", "ize"),
+        ("fulness", "ful"),
+        ("iveness", "ive"),
+        ("ations", "ate"),
+        ("ation", "ate"),
+        ("ingly", ""),
+        ("ies", "y"),
+        ("ied", "y"),
+        ("sses", "ss"),
+        ("ing", ""),
+        ("edly", ""),
+        ("ed", ""),
+        ("ly", ""),
+        ("es", ""),
+        ("s", ""),
+    ];
+    for (suffix, replacement) in SUFFIXES {
+        if let Some(stem) = word.strip_suffix(suffix) {
+            // Keep at least three letters and a vowel of the stem ("string"
+            // isn't "str" + "ing"), leave words like "class" or "speed"
+            // alone, and only drop "es" where "s" alone wouldn't do
+            // ("boxes", but "lifetimes")
+            if stem.len() < 3
+                || !stem.contains(['a', 'e', 'i', 'o', 'u', 'y'])
+                || (*suffix == "s" && stem.ends_with('s'))
+                || (*suffix == "ed" && stem.ends_with('e'))
+                || (*suffix == "es"
+                    && !["s", "x", "z", "ch", "sh"]
+                        .iter()
+                        .any(|s| stem.ends_with(s)))
+            {
+                continue;
+            }
+            let mut stem = format!("{}{}", stem, replacement);
+            // "running" -> "runn" -> "run"
+            let bytes = stem.as_bytes();
+            let n = bytes.len();
+            if replacement.is_empty()
+                && n >= 4
+                && bytes[n - 1] == bytes[n - 2]
+                && !matches!(bytes[n - 1], b'l' | b's' | b'z')
+            {
+                stem.pop();
+            }
+            return stem;
+        }
+    }
+    word.to_string()
+}
+
+/// Cuts an excerpt of about `SNIPPET_LEN` characters around the first word
+/// of `body` matching `terms` and marks every matching word in it
+fn snippet(body: &str, terms: &HashSet<String>) -> String {
+    let matches: Vec<(usize, &str)> = words(body)
+        .filter(|(_, word)| term(word).is_some_and(|t| terms.contains(&t)))
+        .collect();
+
+    let first = matches.first().map(|(offset, _)| *offset).unwrap_or(0);
+    let mut start = first.saturating_sub(SNIPPET_LEN / 4);
+    while !body.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (start + SNIPPET_LEN).min(body.len());
+    while !body.is_char_boundary(end) {
+        end += 1;
+    }
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut at = start;
+    for (offset, word) in matches {
+        if offset < start || offset + word.len() > end {
+            continue;
+        }
+        snippet.push_str(&escape(&body[at..offset]));
+        snippet.push_str("<mark>");
+        snippet.push_str(&escape(word));
+        snippet.push_str("</mark>");
+        at = offset + word.len();
+    }
+    snippet.push_str(&escape(&body[at..end]));
+    if end < body.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question(id: &str, title: &str, content: &str) -> Question {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "title": title,
+            "content": content,
+            "tags": null,
+        }))
+        .unwrap()
+    }
+
+    fn answer(id: &str, question_id: &str, content: &str) -> Answer {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "question_id": question_id,
+            "content": content,
+        }))
+        .unwrap()
+    }
+
+    fn terms(query: &str) -> HashSet<String> {
+        tokenize(query).collect()
+    }
+
+    /// Question ids of the hits, best first
+    fn ranking(index: &Index, query: &str) -> Vec<String> {
+        let (hits, _) = index.search(query, 10, true);
+        hits.into_iter().map(|hit| hit.question_id.0).collect()
+    }
+
+    #[test]
+    fn stem_folds_inflections_together() {
+        for word in ["borrow", "borrows", "borrowing", "borrowed"] {
+            assert_eq!(stem(word), "borrow", "{}", word);
+        }
+        assert_eq!(stem("running"), "run");
+        assert_eq!(stem("studies"), "study");
+        assert_eq!(stem("boxes"), "box");
+        assert_eq!(stem("lifetimes"), "lifetime");
+        assert_eq!(stem("compilation"), "compilate");
+    }
+
+    #[test]
+    fn stem_leaves_what_only_looks_inflected() {
+        assert_eq!(stem("string"), "string");
+        assert_eq!(stem("class"), "class");
+        assert_eq!(stem("speed"), "speed");
+        assert_eq!(stem("bus"), "bus");
+        assert_eq!(stem("naïveness"), "naïveness");
+    }
+
+    #[test]
+    fn tokenize_lowercases_splits_and_drops_stop_words() {
+        let tokens: Vec<String> = tokenize("How do I borrow a Vec<String> in Rust?").collect();
+        assert_eq!(tokens, vec!["borrow", "vec", "string", "rust"]);
+        assert_eq!(tokenize("the and of").count(), 0);
+        assert_eq!(tokenize("").count(), 0);
+    }
+
+    #[test]
+    fn snippet_marks_every_match_and_escapes_html() {
+        let snippet = snippet("Borrowing a <T> & borrowed refs", &terms("borrow"));
+        assert_eq!(
+            snippet,
+            "<mark>Borrowing</mark> a &lt;T&gt; &amp; <mark>borrowed</mark> refs"
+        );
+    }
+
+    #[test]
+    fn snippet_cuts_around_the_first_match() {
+        let body = format!("{} needle {}", "hay ".repeat(100), "hay ".repeat(100));
+        let snippet = snippet(&body, &terms("needle"));
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.contains("<mark>needle</mark>"));
+        assert!(snippet.chars().count() < SNIPPET_LEN + 20);
+    }
+
+    #[test]
+    fn snippet_without_a_match_is_the_start_of_the_body() {
+        assert_eq!(snippet("short body", &terms("needle")), "short body");
+    }
+
+    #[test]
+    fn snippet_never_splits_a_character() {
+        let body = "é".repeat(200) + " needle";
+        let snippet = snippet(&body, &terms("needle"));
+        assert!(snippet.contains("<mark>needle</mark>"));
+    }
+
+    #[test]
+    fn more_occurrences_rank_higher() {
+        let mut index = Index::default();
+        index.add_question(&question("1", "Lifetimes", "a lifetime question"));
+        index.add_question(&question("2", "Traits", "lifetime once, then traits"));
+        index.add_question(&question("3", "Closures", "nothing related"));
+        assert_eq!(ranking(&index, "lifetimes"), vec!["1", "2"]);
+    }
+
+    #[test]
+    fn rare_terms_outweigh_common_ones() {
+        let mut index = Index::default();
+        index.add_question(&question("1", "Rust async", "rust"));
+        index.add_question(&question("2", "Rust tokio", "rust"));
+        index.add_question(&question("3", "Rust warp", "rust"));
+        let (hits, total) = index.search("rust tokio", 10, true);
+        assert_eq!(total, 3);
+        assert_eq!(hits[0].question_id.0, "2");
+        assert!(hits[0].score > hits[1].score);
+    }
+
+    #[test]
+    fn shorter_documents_win_on_equal_counts() {
+        let mut index = Index::default();
+        index.add_question(&question("1", "Macro", "plus many other words besides it"));
+        index.add_question(&question("2", "Macro", "short"));
+        assert_eq!(ranking(&index, "macro"), vec!["2", "1"]);
+    }
+
+    #[test]
+    fn limit_caps_hits_but_not_total() {
+        let mut index = Index::default();
+        for id in ["1", "2", "3"] {
+            index.add_question(&question(id, "Cargo", "cargo"));
+        }
+        let (hits, total) = index.search("cargo", 2, true);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(total, 3);
+        assert_eq!(index.search("the", 10, true).1, 0);
+    }
+
+    #[test]
+    fn answers_are_found_and_removed_with_their_question() {
+        let mut index = Index::default();
+        index.add_question(&question("1", "Iterators", "how to chain"));
+        index.add_answer(&answer("a1", "1", "use zip"), "Iterators");
+        let (hits, _) = index.search("zip", 10, true);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind, HitKind::Answer);
+        assert_eq!(hits[0].title, "Iterators");
+
+        index.add_question(&question("1", "Iterator adapters", "how to chain"));
+        assert_eq!(index.search("zip", 10, true).0[0].title, "Iterator adapters");
+
+        index.remove_question(&QuestionId("1".to_string()));
+        assert_eq!(index.search("zip chain", 10, true).1, 0);
+        assert_eq!(index.total_length, 0);
+        assert!(index.postings.is_empty());
+    }
+
+    #[test]
+    fn answers_can_be_left_out() {
+        let mut index = Index::default();
+        index.add_question(&question("1", "Iterators", "how to chain"));
+        index.add_answer(&answer("a1", "1", "chain with zip"), "Iterators");
+        assert_eq!(index.search("chain", 10, true).1, 2);
+        let (hits, total) = index.search("chain", 10, false);
+        assert_eq!(total, 1);
+        assert_eq!(hits[0].kind, HitKind::Question);
+        assert_eq!(index.search("zip", 10, false).1, 0);
+    }
+
+    #[test]
+    fn reindexing_forgets_old_terms() {
+        let mut index = Index::default();
+        index.add_question(&question("1", "Serde", "json"));
+        index.add_question(&question("1", "Serde", "yaml"));
+        assert_eq!(index.search("json", 10, true).1, 0);
+        assert_eq!(ranking(&index, "yaml"), vec!["1"]);
+    }
+}
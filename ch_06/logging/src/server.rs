@@ -0,0 +1,102 @@
+use handle_errors::with_request_id;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Instant;
+use warp::http::{HeaderMap, HeaderValue};
+use warp::hyper::service::Service;
+use warp::hyper::{Body, Request, Response};
+
+/// Longest `X-Request-Id` taken over from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Headers carrying credentials, logged without their values
+const SECRET_HEADERS: &[&str] = &["authorization", "cookie", "x-api-key"];
+
+/// Handles one request with `service` under its request id and logs it once
+/// answered. The id is the client's `X-Request-Id` if it sent a usable one,
+/// a fresh one otherwise, and goes back in the response's `X-Request-Id`.
+pub async fn handle<S>(
+    mut service: S,
+    remote_addr: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_usable(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let headers = redacted(req.headers());
+    let start = Instant::now();
+
+    // The route tree's future is large, boxing keeps it off the worker's stack
+    let mut response = with_request_id(id.clone(), Box::pin(service.call(req))).await?;
+
+    log::info!(
+        "{} {} {} {} {:?} from {} with {:?}",
+        id,
+        method,
+        path,
+        response.status(),
+        start.elapsed(),
+        remote_addr,
+        headers
+    );
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    Ok(response)
+}
+
+/// Copy of `headers` fit for the log, secrets replaced
+fn redacted(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in SECRET_HEADERS {
+        // `insert` replaces every value of a repeated header
+        if headers.contains_key(*name) {
+            headers.insert(*name, HeaderValue::from_static("<redacted>"));
+        }
+    }
+    headers
+}
+
+/// Ids end up in logs, so only short ones of visible ASCII are kept
+fn is_usable(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_and_the_rest_kept() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer abc.def.ghi"));
+        headers.insert("x-api-key", HeaderValue::from_static("sk_secret"));
+        headers.insert("cookie", HeaderValue::from_static("session=1"));
+        headers.append("cookie", HeaderValue::from_static("session=2"));
+        headers.insert("user-agent", HeaderValue::from_static("curl"));
+
+        let logged = format!("{:?}", redacted(&headers));
+        for secret in ["abc.def.ghi", "sk_secret", "session=1", "session=2"] {
+            assert!(!logged.contains(secret), "{} in {}", secret, logged);
+        }
+        assert_eq!(redacted(&headers)["authorization"], "<redacted>");
+        assert_eq!(redacted(&headers)["user-agent"], "curl");
+    }
+
+    #[test]
+    fn only_short_visible_ids_are_usable() {
+        assert!(is_usable("abc-123"));
+        assert!(!is_usable(""));
+        assert!(!is_usable("has space"));
+        assert!(!is_usable(&"x".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// Reads a file in the keyed shape of `questions.json`: one JSON object
+/// whose keys are the ids of the records stored under them.
+///
+/// Returns `Ok(None)` if the file doesn't exist and an empty list if it is
+/// empty. A file that isn't a JSON object is an error, while entries that
+/// don't parse or whose key disagrees with their `id` are logged and skipped.
+pub fn read_keyed<T: DeserializeOwned>(
+    path: &Path,
+    id: fn(&T) -> &str,
+) -> Result<Option<Vec<T>>, String> {
+    let file = match std::fs::read_to_string(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("can't read {}: {}", path.display(), e)),
+    };
+
+    if file.trim().is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+
+    let entries: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&file)
+        .map_err(|e| format!("{} is not a JSON object: {}", path.display(), e))?;
+
+    let mut records = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        let record: T = match serde_json::from_value(value) {
+            Ok(record) => record,
+            Err(e) => {
+                log::error!("Skipping entry \"{}\" in {}: {}", key, path.display(), e);
+                continue;
+            }
+        };
+        if id(&record) != key {
+            log::error!(
+                "Skipping entry \"{}\" in {}: it has id \"{}\"",
+                key,
+                path.display(),
+                id(&record)
+            );
+            continue;
+        }
+        records.push(record);
+    }
+    Ok(Some(records))
+}
+
+/// Writes `records` to `path` in the same keyed shape `read_keyed` reads.
+/// The JSON goes to a temporary file next to `path` first and is renamed
+/// over it once synced, so a crash mid-write never leaves a torn file.
+pub async fn write_keyed<K, V>(path: &Path, records: &HashMap<K, V>) -> std::io::Result<()>
+where
+    K: Serialize + Eq + Hash,
+    V: Serialize,
+{
+    let json = serde_json::to_vec_pretty(records)?;
+    let tmp = path.with_extension("json.tmp");
+
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(&json).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    tokio::fs::rename(&tmp, path).await
+}
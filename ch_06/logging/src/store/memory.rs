@@ -0,0 +1,662 @@
+use async_trait::async_trait;
+use handle_errors::Error;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+
+use super::{Storage, json_file};
+use crate::types::{
+    answer::{Answer, AnswerId},
+    api_key::{ApiKey, ApiKeyId},
+    question::{Question, QuestionId},
+    revision::Revision,
+    tag::Tag,
+    user::{User, UserId},
+    vote::Vote,
+};
+
+/// Keeps everything in process memory. Without a snapshot directory
+/// nothing survives a restart.
+#[derive(Debug)]
+pub struct MemoryStore {
+    questions: RwLock<HashMap<QuestionId, Question>>,
+    answers: RwLock<HashMap<AnswerId, Answer>>,
+    tags: RwLock<HashMap<String, Tag>>,
+    /// Keyed by `Revision::id`
+    revisions: RwLock<HashMap<String, Revision>>,
+    users: RwLock<HashMap<UserId, User>>,
+    api_keys: RwLock<HashMap<ApiKeyId, ApiKey>>,
+    /// Keyed by `Vote::id`
+    votes: RwLock<HashMap<String, Vote>>,
+    snapshot: Option<Snapshot>,
+}
+
+/// Where and how often the maps get flushed to disk
+#[derive(Debug)]
+struct Snapshot {
+    dir: PathBuf,
+    /// Flush after every mutation instead of on the background interval
+    write_through: bool,
+    /// One `Map::bit` per map changed since the last successful flush
+    dirty: AtomicU8,
+    /// Keeps two flushes from writing the same temp files at once
+    writing: Mutex<()>,
+}
+
+/// The maps, each snapshotted to a file of its own
+#[derive(Debug, Clone, Copy)]
+enum Map {
+    Questions,
+    Answers,
+    Tags,
+    Revisions,
+    Users,
+    ApiKeys,
+    Votes,
+}
+
+impl Map {
+    const ALL: [Map; 7] = [
+        Map::Questions,
+        Map::Answers,
+        Map::Tags,
+        Map::Revisions,
+        Map::Users,
+        Map::ApiKeys,
+        Map::Votes,
+    ];
+
+    fn file(self) -> &'static str {
+        match self {
+            Map::Questions => "questions.json",
+            Map::Answers => "answers.json",
+            Map::Tags => "tags.json",
+            Map::Revisions => "revisions.json",
+            Map::Users => "users.json",
+            Map::ApiKeys => "api_keys.json",
+            Map::Votes => "votes.json",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl MemoryStore {
+    pub fn new(seed: HashMap<QuestionId, Question>) -> Self {
+        MemoryStore {
+            questions: RwLock::new(seed),
+            answers: RwLock::new(HashMap::new()),
+            tags: RwLock::new(HashMap::new()),
+            revisions: RwLock::new(HashMap::new()),
+            users: RwLock::new(HashMap::new()),
+            api_keys: RwLock::new(HashMap::new()),
+            votes: RwLock::new(HashMap::new()),
+            snapshot: None,
+        }
+    }
+
+    /// Restores the maps from the `questions.json`, `answers.json`,
+    /// `tags.json`, `revisions.json`, `users.json`, `api_keys.json` and
+    /// `votes.json` snapshots in `dir`, falling back to `seed` when there is
+    /// no question snapshot yet. Mutations are written back right away
+    /// unless an `interval` is given, in which case a background task
+    /// flushes them.
+    pub fn with_snapshot(
+        seed: HashMap<QuestionId, Question>,
+        dir: &Path,
+        interval: Option<Duration>,
+    ) -> Result<Arc<Self>, Error> {
+        std::fs::create_dir_all(dir).map_err(|e| snapshot_error(dir, e))?;
+
+        let questions = restore(dir, Map::Questions.file(), |q: &Question| &q.id.0, |q| q.id.clone())?
+            .unwrap_or(seed);
+        let answers = restore(dir, Map::Answers.file(), |a: &Answer| &a.id.0, |a| a.id.clone())?
+            .unwrap_or_default();
+        let tags = restore(dir, Map::Tags.file(), |t: &Tag| &t.name, |t| t.name.clone())?
+            .unwrap_or_default();
+        let revisions = restore(dir, Map::Revisions.file(), |r: &Revision| &r.id, |r| r.id.clone())?
+            .unwrap_or_default();
+        let users = restore(dir, Map::Users.file(), |u: &User| &u.id.0, |u| u.id.clone())?
+            .unwrap_or_default();
+        let api_keys = restore(dir, Map::ApiKeys.file(), |k: &ApiKey| &k.id.0, |k| k.id.clone())?
+            .unwrap_or_default();
+        let votes = restore(dir, Map::Votes.file(), |v: &Vote| &v.id, |v| v.id.clone())?
+            .unwrap_or_default();
+        log::info!(
+            "Restored {} questions, {} answers, {} tags, {} revisions, {} users, {} API keys \
+             and {} votes from {}",
+            questions.len(),
+            answers.len(),
+            tags.len(),
+            revisions.len(),
+            users.len(),
+            api_keys.len(),
+            votes.len(),
+            dir.display()
+        );
+
+        let store = Arc::new(MemoryStore {
+            questions: RwLock::new(questions),
+            answers: RwLock::new(answers),
+            tags: RwLock::new(tags),
+            revisions: RwLock::new(revisions),
+            users: RwLock::new(users),
+            api_keys: RwLock::new(api_keys),
+            votes: RwLock::new(votes),
+            snapshot: Some(Snapshot {
+                dir: dir.to_path_buf(),
+                write_through: interval.is_none(),
+                dirty: AtomicU8::new(0),
+                writing: Mutex::new(()),
+            }),
+        });
+
+        if let Some(interval) = interval {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                loop {
+                    ticker.tick().await;
+                    let _ = store.flush().await;
+                }
+            });
+        }
+
+        Ok(store)
+    }
+
+    /// Called after every mutation of `map`
+    async fn changed(&self, map: Map) {
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.dirty.fetch_or(map.bit(), Ordering::SeqCst);
+            if snapshot.write_through {
+                // flush() logs its own failures and leaves the snapshot
+                // dirty, so the next mutation tries again
+                let _ = self.flush().await;
+            }
+        }
+    }
+
+    /// Score of every subject that has votes
+    async fn scores(&self) -> HashMap<String, i64> {
+        let mut scores = HashMap::new();
+        for vote in self.votes.read().await.values() {
+            *scores.entry(vote.subject.clone()).or_insert(0) += vote.direction.value();
+        }
+        scores
+    }
+
+    async fn score(&self, subject: &str) -> i64 {
+        self.votes
+            .read()
+            .await
+            .values()
+            .filter(|v| v.subject == subject)
+            .map(|v| v.direction.value())
+            .sum()
+    }
+
+    /// Writes `map` to its snapshot `path`
+    async fn write(&self, map: Map, path: &Path) -> std::io::Result<()> {
+        match map {
+            Map::Questions => {
+                let questions = self.questions.read().await.clone();
+                json_file::write_keyed(path, &questions).await
+            }
+            Map::Answers => {
+                let answers = self.answers.read().await.clone();
+                json_file::write_keyed(path, &answers).await
+            }
+            Map::Tags => {
+                let tags = self.tags.read().await.clone();
+                json_file::write_keyed(path, &tags).await
+            }
+            Map::Revisions => {
+                let revisions = self.revisions.read().await.clone();
+                json_file::write_keyed(path, &revisions).await
+            }
+            Map::Users => {
+                let users = self.users.read().await.clone();
+                json_file::write_keyed(path, &users).await
+            }
+            Map::ApiKeys => {
+                let api_keys = self.api_keys.read().await.clone();
+                json_file::write_keyed(path, &api_keys).await
+            }
+            Map::Votes => {
+                let votes = self.votes.read().await.clone();
+                json_file::write_keyed(path, &votes).await
+            }
+        }
+    }
+}
+
+/// Reads one map back from its snapshot file in `dir`, if there is one
+fn restore<K, T>(
+    dir: &Path,
+    file: &str,
+    id: fn(&T) -> &str,
+    key: fn(&T) -> K,
+) -> Result<Option<HashMap<K, T>>, Error>
+where
+    K: Eq + Hash,
+    T: DeserializeOwned,
+{
+    match json_file::read_keyed(&dir.join(file), id) {
+        Ok(records) => Ok(records.map(|records| {
+            records
+                .into_iter()
+                .map(|record| (key(&record), record))
+                .collect()
+        })),
+        Err(e) => Err(snapshot_error(dir, e)),
+    }
+}
+
+fn snapshot_error(dir: &Path, e: impl std::fmt::Display) -> Error {
+    log::error!("Snapshot in {} unusable: {}", dir.display(), e);
+    Error::DatabaseQueryError
+}
+
+#[async_trait]
+impl Storage for MemoryStore {
+    async fn get_questions(&self) -> Result<Vec<Question>, Error> {
+        let scores = self.scores().await;
+        let mut questions: Vec<Question> = self.questions.read().await.values().cloned().collect();
+        for q in &mut questions {
+            q.score = scores
+                .get(&Revision::question_subject(&q.id))
+                .copied()
+                .unwrap_or(0);
+        }
+        Ok(questions)
+    }
+
+    async fn get_question(&self, id: &QuestionId) -> Result<Question, Error> {
+        let mut question = match self.questions.read().await.get(id) {
+            Some(q) => q.clone(),
+            None => return Err(Error::QuestionNotFound),
+        };
+        question.score = self.score(&Revision::question_subject(id)).await;
+        Ok(question)
+    }
+
+    async fn add_question(&self, question: Question) -> Result<Question, Error> {
+        match self.questions.write().await.entry(question.id.clone()) {
+            Entry::Occupied(_) => return Err(Error::QuestionAlreadyExists),
+            Entry::Vacant(entry) => entry.insert(question.clone()),
+        };
+        self.changed(Map::Questions).await;
+        Ok(question)
+    }
+
+    async fn update_question(
+        &self,
+        id: &QuestionId,
+        question: Question,
+    ) -> Result<Question, Error> {
+        match self.questions.write().await.get_mut(id) {
+            Some(q) if q.version + 1 != question.version => return Err(Error::EditConflict),
+            Some(q) => *q = question.clone(),
+            None => return Err(Error::QuestionNotFound),
+        }
+        self.changed(Map::Questions).await;
+        Ok(question)
+    }
+
+    async fn delete_question(&self, id: &QuestionId) -> Result<Question, Error> {
+        let deleted = match self.questions.write().await.remove(id) {
+            Some(q) => q,
+            None => return Err(Error::QuestionNotFound),
+        };
+        self.changed(Map::Questions).await;
+        Ok(deleted)
+    }
+
+    async fn get_answers(&self, question_id: &QuestionId) -> Result<Vec<Answer>, Error> {
+        let scores = self.scores().await;
+        let mut answers: Vec<Answer> = self
+            .answers
+            .read()
+            .await
+            .values()
+            .filter(|a| &a.question_id == question_id)
+            .cloned()
+            .collect();
+        for a in &mut answers {
+            a.score = scores
+                .get(&Revision::answer_subject(&a.id))
+                .copied()
+                .unwrap_or(0);
+        }
+        Ok(answers)
+    }
+
+    async fn answer_counts(&self) -> Result<HashMap<QuestionId, usize>, Error> {
+        let mut counts = HashMap::new();
+        for answer in self.answers.read().await.values() {
+            if answer.deleted_at.is_some() {
+                continue;
+            }
+            *counts.entry(answer.question_id.clone()).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    async fn get_answer(&self, id: &AnswerId) -> Result<Answer, Error> {
+        let mut answer = match self.answers.read().await.get(id) {
+            Some(a) => a.clone(),
+            None => return Err(Error::AnswerNotFound),
+        };
+        answer.score = self.score(&Revision::answer_subject(id)).await;
+        Ok(answer)
+    }
+
+    async fn add_answer(&self, answer: Answer) -> Result<Answer, Error> {
+        match self.answers.write().await.entry(answer.id.clone()) {
+            Entry::Occupied(_) => return Err(Error::AnswerAlreadyExists),
+            Entry::Vacant(entry) => entry.insert(answer.clone()),
+        };
+        self.changed(Map::Answers).await;
+        Ok(answer)
+    }
+
+    async fn update_answer(&self, id: &AnswerId, answer: Answer) -> Result<Answer, Error> {
+        match self.answers.write().await.get_mut(id) {
+            Some(a) => *a = answer.clone(),
+            None => return Err(Error::AnswerNotFound),
+        }
+        self.changed(Map::Answers).await;
+        Ok(answer)
+    }
+
+    async fn delete_answer(&self, id: &AnswerId) -> Result<Answer, Error> {
+        let deleted = match self.answers.write().await.remove(id) {
+            Some(a) => a,
+            None => return Err(Error::AnswerNotFound),
+        };
+        self.changed(Map::Answers).await;
+        Ok(deleted)
+    }
+
+    async fn delete_answers(&self, question_id: &QuestionId) -> Result<usize, Error> {
+        let deleted = {
+            let mut answers = self.answers.write().await;
+            let before = answers.len();
+            answers.retain(|_, a| &a.question_id != question_id);
+            before - answers.len()
+        };
+        if deleted > 0 {
+            self.changed(Map::Answers).await;
+        }
+        Ok(deleted)
+    }
+
+    async fn get_revisions(&self, subject: &str) -> Result<Vec<Revision>, Error> {
+        let mut revisions: Vec<Revision> = self
+            .revisions
+            .read()
+            .await
+            .values()
+            .filter(|r| r.subject == subject)
+            .cloned()
+            .collect();
+        revisions.sort_by_key(|r| r.number);
+        Ok(revisions)
+    }
+
+    async fn add_revision(&self, revision: Revision) -> Result<Revision, Error> {
+        match self.revisions.write().await.entry(revision.id.clone()) {
+            Entry::Occupied(_) => return Err(Error::EditConflict),
+            Entry::Vacant(entry) => entry.insert(revision.clone()),
+        };
+        self.changed(Map::Revisions).await;
+        Ok(revision)
+    }
+
+    async fn delete_revisions(&self, subject: &str) -> Result<usize, Error> {
+        let deleted = {
+            let mut revisions = self.revisions.write().await;
+            let before = revisions.len();
+            revisions.retain(|_, r| r.subject != subject);
+            before - revisions.len()
+        };
+        if deleted > 0 {
+            self.changed(Map::Revisions).await;
+        }
+        Ok(deleted)
+    }
+
+    async fn get_tags(&self) -> Result<Vec<Tag>, Error> {
+        Ok(self.tags.read().await.values().cloned().collect())
+    }
+
+    async fn put_tag(&self, tag: Tag) -> Result<Tag, Error> {
+        self.tags
+            .write()
+            .await
+            .insert(tag.name.clone(), tag.clone());
+        self.changed(Map::Tags).await;
+        Ok(tag)
+    }
+
+    async fn delete_tag(&self, name: &str) -> Result<Tag, Error> {
+        let deleted = match self.tags.write().await.remove(name) {
+            Some(t) => t,
+            None => return Err(Error::TagNotFound),
+        };
+        self.changed(Map::Tags).await;
+        Ok(deleted)
+    }
+
+    async fn add_user(&self, user: User) -> Result<User, Error> {
+        {
+            let mut users = self.users.write().await;
+            if users.values().any(|u| u.email == user.email) {
+                return Err(Error::EmailTaken);
+            }
+            users.insert(user.id.clone(), user.clone());
+        }
+        self.changed(Map::Users).await;
+        Ok(user)
+    }
+
+    async fn get_user(&self, id: &UserId) -> Result<User, Error> {
+        match self.users.read().await.get(id) {
+            Some(u) => Ok(u.clone()),
+            None => Err(Error::UserNotFound),
+        }
+    }
+
+    async fn update_user(&self, user: User) -> Result<User, Error> {
+        match self.users.write().await.get_mut(&user.id) {
+            Some(u) => *u = user.clone(),
+            None => return Err(Error::UserNotFound),
+        }
+        self.changed(Map::Users).await;
+        Ok(user)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<User, Error> {
+        match self.users.read().await.values().find(|u| u.email == email) {
+            Some(u) => Ok(u.clone()),
+            None => Err(Error::UserNotFound),
+        }
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        Ok(self.api_keys.read().await.values().cloned().collect())
+    }
+
+    async fn get_api_key(&self, id: &ApiKeyId) -> Result<ApiKey, Error> {
+        match self.api_keys.read().await.get(id) {
+            Some(k) => Ok(k.clone()),
+            None => Err(Error::ApiKeyNotFound),
+        }
+    }
+
+    async fn get_api_key_by_hash(&self, hash: &str) -> Result<ApiKey, Error> {
+        match self.api_keys.read().await.values().find(|k| k.hash == hash) {
+            Some(k) => Ok(k.clone()),
+            None => Err(Error::ApiKeyNotFound),
+        }
+    }
+
+    async fn add_api_key(&self, key: ApiKey) -> Result<ApiKey, Error> {
+        self.api_keys
+            .write()
+            .await
+            .insert(key.id.clone(), key.clone());
+        self.changed(Map::ApiKeys).await;
+        Ok(key)
+    }
+
+    async fn update_api_key(&self, key: ApiKey) -> Result<ApiKey, Error> {
+        match self.api_keys.write().await.get_mut(&key.id) {
+            Some(k) => *k = key.clone(),
+            None => return Err(Error::ApiKeyNotFound),
+        }
+        self.changed(Map::ApiKeys).await;
+        Ok(key)
+    }
+
+    async fn put_vote(&self, vote: Vote) -> Result<Vote, Error> {
+        self.votes
+            .write()
+            .await
+            .insert(vote.id.clone(), vote.clone());
+        self.changed(Map::Votes).await;
+        Ok(vote)
+    }
+
+    async fn delete_vote(&self, id: &str) -> Result<Option<Vote>, Error> {
+        let deleted = self.votes.write().await.remove(id);
+        if deleted.is_some() {
+            self.changed(Map::Votes).await;
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_votes(&self, subject: &str) -> Result<usize, Error> {
+        let deleted = {
+            let mut votes = self.votes.write().await;
+            let before = votes.len();
+            votes.retain(|_, v| v.subject != subject);
+            before - votes.len()
+        };
+        if deleted > 0 {
+            self.changed(Map::Votes).await;
+        }
+        Ok(deleted)
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        let Some(snapshot) = &self.snapshot else {
+            return Ok(());
+        };
+        let _writing = snapshot.writing.lock().await;
+        // Clear the flags before reading the maps, so a mutation that lands
+        // while we write marks its map dirty again
+        let dirty = snapshot.dirty.swap(0, Ordering::SeqCst);
+
+        for map in Map::ALL {
+            if dirty & map.bit() == 0 {
+                continue;
+            }
+            if let Err(e) = self.write(map, &snapshot.dir.join(map.file())).await {
+                snapshot.dirty.fetch_or(dirty, Ordering::SeqCst);
+                return Err(snapshot_error(&snapshot.dir, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn snapshot_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("memory-store-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn api_key() -> ApiKey {
+        ApiKey {
+            id: ApiKeyId("k1".to_string()),
+            name: "bot".to_string(),
+            prefix: "qak_abc".to_string(),
+            hash: "0123abcd".to_string(),
+            scopes: Vec::new(),
+            owner: UserId("u1".to_string()),
+            created_at: Utc::now(),
+            last_used_at: None,
+            revoked_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn write_through_only_writes_the_changed_map() {
+        let dir = snapshot_dir();
+        let store = MemoryStore::with_snapshot(HashMap::new(), &dir, None).unwrap();
+
+        store.add_api_key(api_key()).await.unwrap();
+        assert_eq!(files(&dir), vec!["api_keys.json"]);
+
+        // Recording a key's use rewrites the keys and nothing else
+        let mut used = api_key();
+        used.last_used_at = Some(Utc::now());
+        store.update_api_key(used).await.unwrap();
+        assert_eq!(files(&dir), vec!["api_keys.json"]);
+
+        let question = serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "title": "Title",
+            "content": "Content",
+        }))
+        .unwrap();
+        store.add_question(question).await.unwrap();
+        assert_eq!(files(&dir), vec!["api_keys.json", "questions.json"]);
+
+        let restored = MemoryStore::with_snapshot(HashMap::new(), &dir, None).unwrap();
+        let key = restored.get_api_key(&ApiKeyId("k1".to_string())).await.unwrap();
+        assert!(key.last_used_at.is_some());
+        assert_eq!(restored.get_questions().await.unwrap().len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn interval_flush_writes_what_changed_since_the_last_one() {
+        let dir = snapshot_dir();
+        let store =
+            MemoryStore::with_snapshot(HashMap::new(), &dir, Some(Duration::from_secs(3600)))
+                .unwrap();
+
+        store.add_api_key(api_key()).await.unwrap();
+        store.flush().await.unwrap();
+        assert_eq!(files(&dir), vec!["api_keys.json"]);
+
+        // Nothing changed since, so nothing gets written
+        std::fs::remove_file(dir.join("api_keys.json")).unwrap();
+        store.flush().await.unwrap();
+        assert!(files(&dir).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
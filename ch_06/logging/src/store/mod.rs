@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use handle_errors::Error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::{Backend, Config};
+use crate::types::{
+    answer::{Answer, AnswerId},
+    api_key::{ApiKey, ApiKeyId},
+    question::{Question, QuestionId},
+    revision::Revision,
+    tag::Tag,
+    user::{User, UserId},
+    vote::Vote,
+};
+
+mod json_file;
+pub mod memory;
+pub mod purge;
+mod seed;
+pub mod sqlite;
+/// What every backend has to do alike, checked against each of them
+#[cfg(test)]
+mod tests;
+
+use memory::MemoryStore;
+use sqlite::SqliteStore;
+
+/// Handle to the storage backend that the route handlers share.
+pub type Store = Arc<dyn Storage>;
+
+/// Everything the route handlers need from a storage backend.
+/// Lookups of missing records come back as `Error::QuestionNotFound`
+/// or `Error::AnswerNotFound`, and adding a record under a taken id as
+/// `Error::QuestionAlreadyExists` or `Error::AnswerAlreadyExists`, so
+/// handlers can hand the error straight to `warp::reject::custom`.
+/// Questions and answers come back with their `score` filled in.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get_questions(&self) -> Result<Vec<Question>, Error>;
+    async fn get_question(&self, id: &QuestionId) -> Result<Question, Error>;
+    async fn add_question(&self, question: Question) -> Result<Question, Error>;
+    /// Replaces the question, but only if the stored one is the version
+    /// before `question.version`. Otherwise another write got in first,
+    /// and the update fails with `Error::EditConflict`.
+    async fn update_question(&self, id: &QuestionId, question: Question)
+    -> Result<Question, Error>;
+    async fn delete_question(&self, id: &QuestionId) -> Result<Question, Error>;
+    async fn get_answers(&self, question_id: &QuestionId) -> Result<Vec<Answer>, Error>;
+    /// Number of answers per question, not counting deleted ones. Questions
+    /// without any are left out.
+    async fn answer_counts(&self) -> Result<HashMap<QuestionId, usize>, Error>;
+    async fn get_answer(&self, id: &AnswerId) -> Result<Answer, Error>;
+    async fn add_answer(&self, answer: Answer) -> Result<Answer, Error>;
+    async fn update_answer(&self, id: &AnswerId, answer: Answer) -> Result<Answer, Error>;
+    async fn delete_answer(&self, id: &AnswerId) -> Result<Answer, Error>;
+    /// Deletes every answer to the question, returning how many there were.
+    async fn delete_answers(&self, question_id: &QuestionId) -> Result<usize, Error>;
+    /// Revisions of `subject` (`questions/{id}` or `answers/{id}`), oldest
+    /// first.
+    async fn get_revisions(&self, subject: &str) -> Result<Vec<Revision>, Error>;
+    /// Adding a revision whose number is taken fails with
+    /// `Error::EditConflict`, as two edits raced for it.
+    async fn add_revision(&self, revision: Revision) -> Result<Revision, Error>;
+    /// Deletes the revisions of `subject`, returning how many there were.
+    async fn delete_revisions(&self, subject: &str) -> Result<usize, Error>;
+    /// The tag catalog. Tags only used by questions aren't part of it.
+    async fn get_tags(&self) -> Result<Vec<Tag>, Error>;
+    /// Adds `tag` to the catalog, or replaces the entry of the same name.
+    async fn put_tag(&self, tag: Tag) -> Result<Tag, Error>;
+    /// Missing tags come back as `Error::TagNotFound`.
+    async fn delete_tag(&self, name: &str) -> Result<Tag, Error>;
+    /// Adding a user whose email is taken fails with `Error::EmailTaken`.
+    async fn add_user(&self, user: User) -> Result<User, Error>;
+    /// Missing users come back as `Error::UserNotFound`.
+    async fn get_user(&self, id: &UserId) -> Result<User, Error>;
+    async fn update_user(&self, user: User) -> Result<User, Error>;
+    /// Looks up a user by normalized email, `Error::UserNotFound` if none
+    /// has it.
+    async fn get_user_by_email(&self, email: &str) -> Result<User, Error>;
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>, Error>;
+    /// Missing keys come back as `Error::ApiKeyNotFound`.
+    async fn get_api_key(&self, id: &ApiKeyId) -> Result<ApiKey, Error>;
+    /// Looks up a key by the hash of its secret, `Error::ApiKeyNotFound` if
+    /// no key has it.
+    async fn get_api_key_by_hash(&self, hash: &str) -> Result<ApiKey, Error>;
+    async fn add_api_key(&self, key: ApiKey) -> Result<ApiKey, Error>;
+    async fn update_api_key(&self, key: ApiKey) -> Result<ApiKey, Error>;
+    /// Adds `vote`, or replaces the one with the same id.
+    async fn put_vote(&self, vote: Vote) -> Result<Vote, Error>;
+    /// Deletes the vote with `id`, returning it, or `None` if there was none.
+    async fn delete_vote(&self, id: &str) -> Result<Option<Vote>, Error>;
+    /// Deletes the votes on `subject`, returning how many there were.
+    async fn delete_votes(&self, subject: &str) -> Result<usize, Error>;
+
+    /// Writes out anything the backend still holds only in memory.
+    async fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Opens the backend selected in the configuration.
+pub fn from_config(config: &Config) -> Result<Store, Error> {
+    let seed = seed::load(&config.seed_path);
+    match config.backend {
+        Backend::Memory => match &config.snapshot_dir {
+            Some(dir) => Ok(MemoryStore::with_snapshot(
+                seed,
+                Path::new(dir),
+                config.snapshot_interval,
+            )?),
+            None => Ok(Arc::new(MemoryStore::new(seed))),
+        },
+        Backend::Sqlite => Ok(Arc::new(SqliteStore::open(&config.database_path, seed)?)),
+    }
+}
+
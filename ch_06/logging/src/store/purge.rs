@@ -0,0 +1,62 @@
+use chrono::Utc;
+use handle_errors::Error;
+use std::time::Duration;
+
+use super::Store;
+use crate::types::revision::Revision;
+
+/// Longest the purge waits between two runs
+const MAX_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purges soft-deleted questions and answers once they have been in the
+/// trash for longer than `retention`, checking at least every hour.
+pub fn spawn(store: Store, retention: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(retention.min(MAX_INTERVAL));
+        loop {
+            ticker.tick().await;
+            match purge(&store, retention).await {
+                Ok((0, 0)) => {}
+                Ok((questions, answers)) => log::info!(
+                    "Purged {} questions and {} answers from the trash",
+                    questions,
+                    answers
+                ),
+                Err(e) => log::error!("Can't purge the trash: {}", e),
+            }
+        }
+    });
+}
+
+/// Deletes what was soft-deleted before `retention` ago for good, with its
+/// revisions and votes. Returns how many questions and answers went.
+pub async fn purge(store: &Store, retention: Duration) -> Result<(usize, usize), Error> {
+    let retention = chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::MAX);
+    let cutoff = Utc::now().checked_sub_signed(retention);
+    let expired = |deleted_at: Option<_>| match (deleted_at, cutoff) {
+        (Some(deleted_at), Some(cutoff)) => deleted_at < cutoff,
+        _ => false,
+    };
+
+    let (mut questions, mut answers) = (0, 0);
+    for question in store.get_questions().await? {
+        let purge_question = expired(question.deleted_at);
+        for answer in store.get_answers(&question.id).await? {
+            if purge_question || expired(answer.deleted_at) {
+                let subject = Revision::answer_subject(&answer.id);
+                store.delete_answer(&answer.id).await?;
+                store.delete_revisions(&subject).await?;
+                store.delete_votes(&subject).await?;
+                answers += 1;
+            }
+        }
+        if purge_question {
+            let subject = Revision::question_subject(&question.id);
+            store.delete_question(&question.id).await?;
+            store.delete_revisions(&subject).await?;
+            store.delete_votes(&subject).await?;
+            questions += 1;
+        }
+    }
+    Ok((questions, answers))
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::json_file;
+use crate::types::question::{Question, QuestionId};
+
+/// Reads the seed questions from `path`, in the keyed shape of
+/// `questions.json`. A missing, empty or malformed file gives an empty
+/// seed. Entries that don't describe a valid question are logged and
+/// skipped, so one bad entry doesn't keep the service from starting.
+pub fn load(path: &str) -> HashMap<QuestionId, Question> {
+    let questions = match json_file::read_keyed(Path::new(path), |q: &Question| &q.id.0) {
+        Ok(Some(questions)) => questions,
+        Ok(None) => {
+            log::warn!("Seed file {} not found, starting without questions", path);
+            return HashMap::new();
+        }
+        Err(e) => {
+            log::error!("Can't load seed questions: {}", e);
+            return HashMap::new();
+        }
+    };
+
+    log::info!("Loaded {} seed questions from {}", questions.len(), path);
+    questions.into_iter().map(|q| (q.id.clone(), q)).collect()
+}
@@ -0,0 +1,615 @@
+use async_trait::async_trait;
+use handle_errors::Error;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::Storage;
+use crate::types::{
+    answer::{Answer, AnswerId},
+    api_key::{ApiKey, ApiKeyId},
+    question::{Question, QuestionId},
+    revision::Revision,
+    tag::Tag,
+    user::{User, UserId},
+    vote::Vote,
+};
+
+/// Embedded SQLite database. Records are kept as JSON documents next to
+/// the columns we look them up by, so adding a field to `Question` or
+/// `Answer` doesn't need a schema migration.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path`. The `seed` questions are
+    /// only inserted into a database that has no questions yet.
+    pub fn open(path: &str, seed: HashMap<QuestionId, Question>) -> Result<Self, Error> {
+        let conn = Connection::open(path).map_err(db_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS questions (
+                 id   TEXT PRIMARY KEY,
+                 data TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS answers (
+                 id          TEXT PRIMARY KEY,
+                 question_id TEXT NOT NULL,
+                 data        TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS answers_question_id ON answers (question_id);
+             CREATE TABLE IF NOT EXISTS tags (
+                 name TEXT PRIMARY KEY,
+                 data TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS revisions (
+                 subject TEXT NOT NULL,
+                 number  INTEGER NOT NULL,
+                 data    TEXT NOT NULL,
+                 PRIMARY KEY (subject, number)
+             );
+             CREATE TABLE IF NOT EXISTS users (
+                 id    TEXT PRIMARY KEY,
+                 email TEXT NOT NULL UNIQUE,
+                 data  TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS api_keys (
+                 id   TEXT PRIMARY KEY,
+                 hash TEXT NOT NULL UNIQUE,
+                 data TEXT NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS votes (
+                 id      TEXT PRIMARY KEY,
+                 subject TEXT NOT NULL,
+                 value   INTEGER NOT NULL,
+                 data    TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS votes_subject ON votes (subject);",
+        )
+        .map_err(db_error)?;
+
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM questions", [], |row| row.get(0))
+            .map_err(db_error)?;
+        if count == 0 {
+            for question in seed.into_values() {
+                conn.execute(
+                    "INSERT INTO questions (id, data) VALUES (?1, ?2)",
+                    params![question.id.0, to_json(&question)?],
+                )
+                .map_err(db_error)?;
+            }
+        }
+
+        Ok(SqliteStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` against the connection on the blocking thread pool,
+    /// since rusqlite calls would otherwise stall the async runtime.
+    async fn call<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T, Error> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|_| Error::DatabaseQueryError)?;
+            f(&conn)
+        })
+        .await
+        .map_err(|e| {
+            log::error!("SQLite task failed: {}", e);
+            Error::DatabaseQueryError
+        })?
+    }
+}
+
+fn db_error(e: rusqlite::Error) -> Error {
+    log::error!("SQLite error: {}", e);
+    Error::DatabaseQueryError
+}
+
+/// Maps a primary key or unique clash on insert to `exists`
+fn insert_error(e: rusqlite::Error, exists: Error) -> Error {
+    match e.sqlite_error_code() {
+        Some(rusqlite::ErrorCode::ConstraintViolation) => exists,
+        _ => db_error(e),
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(|e| {
+        log::error!("Cannot serialize record: {}", e);
+        Error::DatabaseQueryError
+    })
+}
+
+/// Subquery for the score of each row of `table`, `questions` or `answers`.
+/// Their names double as the prefix of the subjects votes are cast on.
+fn score_sql(table: &str) -> String {
+    format!(
+        "(SELECT COALESCE(SUM(votes.value), 0) FROM votes
+          WHERE votes.subject = '{0}/' || {0}.id)",
+        table
+    )
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(data: &str) -> Result<T, Error> {
+    serde_json::from_str(data).map_err(|e| {
+        log::error!("Cannot deserialize record: {}", e);
+        Error::DatabaseQueryError
+    })
+}
+
+/// Deserializes a `(data, score)` row, putting the score where `score` points
+fn scored<T: serde::de::DeserializeOwned>(
+    (data, score): (String, i64),
+    field: fn(&mut T) -> &mut i64,
+) -> Result<T, Error> {
+    let mut record = from_json(&data)?;
+    *field(&mut record) = score;
+    Ok(record)
+}
+
+#[async_trait]
+impl Storage for SqliteStore {
+    async fn get_questions(&self) -> Result<Vec<Question>, Error> {
+        self.call(|conn| {
+            let sql = format!("SELECT data, {} FROM questions", score_sql("questions"));
+            let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+                .map_err(db_error)?;
+            rows.map(|row| scored(row.map_err(db_error)?, |q: &mut Question| &mut q.score))
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_question(&self, id: &QuestionId) -> Result<Question, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let sql = format!(
+                "SELECT data, {} FROM questions WHERE id = ?1",
+                score_sql("questions")
+            );
+            let row: Option<(String, i64)> = conn
+                .query_row(&sql, [id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()
+                .map_err(db_error)?;
+            match row {
+                Some(row) => scored(row, |q: &mut Question| &mut q.score),
+                None => Err(Error::QuestionNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn add_question(&self, question: Question) -> Result<Question, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO questions (id, data) VALUES (?1, ?2)",
+                params![question.id.0, to_json(&question)?],
+            )
+            .map_err(|e| insert_error(e, Error::QuestionAlreadyExists))?;
+            Ok(question)
+        })
+        .await
+    }
+
+    async fn update_question(
+        &self,
+        id: &QuestionId,
+        question: Question,
+    ) -> Result<Question, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let updated = conn
+                .execute(
+                    "UPDATE questions SET data = ?2
+                     WHERE id = ?1 AND COALESCE(data ->> '$.version', 1) = ?3",
+                    params![id, to_json(&question)?, question.version.saturating_sub(1) as i64],
+                )
+                .map_err(db_error)?;
+            if updated > 0 {
+                return Ok(question);
+            }
+            let exists = conn
+                .query_row("SELECT 1 FROM questions WHERE id = ?1", [&id], |_| Ok(()))
+                .optional()
+                .map_err(db_error)?;
+            match exists {
+                Some(()) => Err(Error::EditConflict),
+                None => Err(Error::QuestionNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn delete_question(&self, id: &QuestionId) -> Result<Question, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row(
+                    "DELETE FROM questions WHERE id = ?1 RETURNING data",
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::QuestionNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn get_answers(&self, question_id: &QuestionId) -> Result<Vec<Answer>, Error> {
+        let question_id = question_id.0.clone();
+        self.call(move |conn| {
+            let sql = format!(
+                "SELECT data, {} FROM answers WHERE question_id = ?1",
+                score_sql("answers")
+            );
+            let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+            let rows = stmt
+                .query_map([question_id], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))
+                .map_err(db_error)?;
+            rows.map(|row| scored(row.map_err(db_error)?, |a: &mut Answer| &mut a.score))
+                .collect()
+        })
+        .await
+    }
+
+    async fn answer_counts(&self) -> Result<HashMap<QuestionId, usize>, Error> {
+        self.call(|conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT question_id, COUNT(*) FROM answers
+                     WHERE data ->> '$.deleted_at' IS NULL
+                     GROUP BY question_id",
+                )
+                .map_err(db_error)?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((QuestionId(row.get(0)?), row.get::<_, i64>(1)? as usize))
+                })
+                .map_err(db_error)?;
+            rows.map(|row| row.map_err(db_error)).collect()
+        })
+        .await
+    }
+
+    async fn get_answer(&self, id: &AnswerId) -> Result<Answer, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let sql = format!(
+                "SELECT data, {} FROM answers WHERE id = ?1",
+                score_sql("answers")
+            );
+            let row: Option<(String, i64)> = conn
+                .query_row(&sql, [id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()
+                .map_err(db_error)?;
+            match row {
+                Some(row) => scored(row, |a: &mut Answer| &mut a.score),
+                None => Err(Error::AnswerNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn add_answer(&self, answer: Answer) -> Result<Answer, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO answers (id, question_id, data) VALUES (?1, ?2, ?3)",
+                params![answer.id.0, answer.question_id.0, to_json(&answer)?],
+            )
+            .map_err(|e| insert_error(e, Error::AnswerAlreadyExists))?;
+            Ok(answer)
+        })
+        .await
+    }
+
+    async fn update_answer(&self, id: &AnswerId, answer: Answer) -> Result<Answer, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let updated = conn
+                .execute(
+                    "UPDATE answers SET question_id = ?2, data = ?3 WHERE id = ?1",
+                    params![id, answer.question_id.0, to_json(&answer)?],
+                )
+                .map_err(db_error)?;
+            match updated {
+                0 => Err(Error::AnswerNotFound),
+                _ => Ok(answer),
+            }
+        })
+        .await
+    }
+
+    async fn delete_answer(&self, id: &AnswerId) -> Result<Answer, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row(
+                    "DELETE FROM answers WHERE id = ?1 RETURNING data",
+                    [id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::AnswerNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn delete_answers(&self, question_id: &QuestionId) -> Result<usize, Error> {
+        let question_id = question_id.0.clone();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM answers WHERE question_id = ?1", [question_id])
+                .map_err(db_error)
+        })
+        .await
+    }
+
+    async fn get_revisions(&self, subject: &str) -> Result<Vec<Revision>, Error> {
+        let subject = subject.to_string();
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT data FROM revisions WHERE subject = ?1 ORDER BY number")
+                .map_err(db_error)?;
+            let rows = stmt
+                .query_map([subject], |row| row.get::<_, String>(0))
+                .map_err(db_error)?;
+            rows.map(|data| from_json(&data.map_err(db_error)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn add_revision(&self, revision: Revision) -> Result<Revision, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO revisions (subject, number, data) VALUES (?1, ?2, ?3)",
+                params![revision.subject, revision.number as i64, to_json(&revision)?],
+            )
+            .map_err(|e| insert_error(e, Error::EditConflict))?;
+            Ok(revision)
+        })
+        .await
+    }
+
+    async fn delete_revisions(&self, subject: &str) -> Result<usize, Error> {
+        let subject = subject.to_string();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM revisions WHERE subject = ?1", [subject])
+                .map_err(db_error)
+        })
+        .await
+    }
+
+    async fn get_tags(&self) -> Result<Vec<Tag>, Error> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM tags").map_err(db_error)?;
+            let rows = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(db_error)?;
+            rows.map(|data| from_json(&data.map_err(db_error)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn put_tag(&self, tag: Tag) -> Result<Tag, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO tags (name, data) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET data = excluded.data",
+                params![tag.name, to_json(&tag)?],
+            )
+            .map_err(db_error)?;
+            Ok(tag)
+        })
+        .await
+    }
+
+    async fn delete_tag(&self, name: &str) -> Result<Tag, Error> {
+        let name = name.to_string();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row(
+                    "DELETE FROM tags WHERE name = ?1 RETURNING data",
+                    [name],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::TagNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn add_user(&self, user: User) -> Result<User, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO users (id, email, data) VALUES (?1, ?2, ?3)",
+                params![user.id.0, user.email, to_json(&user)?],
+            )
+            .map_err(|e| insert_error(e, Error::EmailTaken))?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn get_user(&self, id: &UserId) -> Result<User, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row("SELECT data FROM users WHERE id = ?1", [id], |row| row.get(0))
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::UserNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn update_user(&self, user: User) -> Result<User, Error> {
+        self.call(move |conn| {
+            let updated = conn
+                .execute(
+                    "UPDATE users SET email = ?2, data = ?3 WHERE id = ?1",
+                    params![user.id.0, user.email, to_json(&user)?],
+                )
+                .map_err(|e| insert_error(e, Error::EmailTaken))?;
+            match updated {
+                0 => Err(Error::UserNotFound),
+                _ => Ok(user),
+            }
+        })
+        .await
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<User, Error> {
+        let email = email.to_string();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row("SELECT data FROM users WHERE email = ?1", [email], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::UserNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        self.call(|conn| {
+            let mut stmt = conn.prepare("SELECT data FROM api_keys").map_err(db_error)?;
+            let rows = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(db_error)?;
+            rows.map(|data| from_json(&data.map_err(db_error)?))
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_api_key(&self, id: &ApiKeyId) -> Result<ApiKey, Error> {
+        let id = id.0.clone();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row("SELECT data FROM api_keys WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::ApiKeyNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn get_api_key_by_hash(&self, hash: &str) -> Result<ApiKey, Error> {
+        let hash = hash.to_string();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row("SELECT data FROM api_keys WHERE hash = ?1", [hash], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data),
+                None => Err(Error::ApiKeyNotFound),
+            }
+        })
+        .await
+    }
+
+    async fn add_api_key(&self, key: ApiKey) -> Result<ApiKey, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO api_keys (id, hash, data) VALUES (?1, ?2, ?3)",
+                params![key.id.0, key.hash, to_json(&key)?],
+            )
+            .map_err(db_error)?;
+            Ok(key)
+        })
+        .await
+    }
+
+    async fn update_api_key(&self, key: ApiKey) -> Result<ApiKey, Error> {
+        self.call(move |conn| {
+            let updated = conn
+                .execute(
+                    "UPDATE api_keys SET data = ?2 WHERE id = ?1",
+                    params![key.id.0, to_json(&key)?],
+                )
+                .map_err(db_error)?;
+            match updated {
+                0 => Err(Error::ApiKeyNotFound),
+                _ => Ok(key),
+            }
+        })
+        .await
+    }
+
+    async fn put_vote(&self, vote: Vote) -> Result<Vote, Error> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO votes (id, subject, value, data) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (id) DO UPDATE SET value = excluded.value, data = excluded.data",
+                params![vote.id, vote.subject, vote.direction.value(), to_json(&vote)?],
+            )
+            .map_err(db_error)?;
+            Ok(vote)
+        })
+        .await
+    }
+
+    async fn delete_vote(&self, id: &str) -> Result<Option<Vote>, Error> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            let data: Option<String> = conn
+                .query_row("DELETE FROM votes WHERE id = ?1 RETURNING data", [id], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(db_error)?;
+            match data {
+                Some(data) => from_json(&data).map(Some),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+
+    async fn delete_votes(&self, subject: &str) -> Result<usize, Error> {
+        let subject = subject.to_string();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM votes WHERE subject = ?1", [subject])
+                .map_err(db_error)
+        })
+        .await
+    }
+}
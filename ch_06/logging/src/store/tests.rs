@@ -0,0 +1,302 @@
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::*;
+use crate::types::vote::Direction;
+
+fn memory() -> Store {
+    Arc::new(MemoryStore::new(HashMap::new()))
+}
+
+fn sqlite() -> Store {
+    Arc::new(SqliteStore::open(":memory:", HashMap::new()).unwrap())
+}
+
+/// Runs every listed check against both backends
+macro_rules! backend_tests {
+    ($($check:ident),* $(,)?) => {
+        mod memory_store {
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    super::$check(super::memory()).await;
+                }
+            )*
+        }
+
+        mod sqlite_store {
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    super::$check(super::sqlite()).await;
+                }
+            )*
+        }
+    };
+}
+
+backend_tests!(
+    updates_need_the_previous_version,
+    missing_records_are_not_found,
+    taken_ids_are_refused,
+    soft_deleted_records_stay_visible,
+    answer_counts_skip_deleted_answers,
+    scores_add_up_the_votes,
+    votes_on_answers_dont_score_questions,
+    revision_numbers_are_unique,
+    users_and_api_keys_are_found_by_email_and_hash,
+);
+
+fn question(id: &str) -> Question {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "title": "Title",
+        "content": "Content",
+        "tags": ["rust"],
+    }))
+    .unwrap()
+}
+
+fn answer(id: &str, question_id: &str) -> Answer {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "question_id": question_id,
+        "content": "Content",
+    }))
+    .unwrap()
+}
+
+fn user(id: &str, email: &str) -> User {
+    User {
+        id: UserId(id.to_string()),
+        email: email.to_string(),
+        password: String::new(),
+        role: Default::default(),
+        created_at: Utc::now(),
+    }
+}
+
+fn qid(id: &str) -> QuestionId {
+    QuestionId(id.to_string())
+}
+
+fn aid(id: &str) -> AnswerId {
+    AnswerId(id.to_string())
+}
+
+fn vote(subject: String, voter: &str, direction: Direction) -> Vote {
+    Vote::new(subject, UserId(voter.to_string()), direction)
+}
+
+async fn updates_need_the_previous_version(store: Store) {
+    let mut q = store.add_question(question("1")).await.unwrap();
+    assert_eq!(q.version, 1);
+
+    q.title = "Edited".to_string();
+    q.version = 2;
+    store.update_question(&qid("1"), q.clone()).await.unwrap();
+    assert_eq!(store.get_question(&qid("1")).await.unwrap().title, "Edited");
+
+    // A second writer that also read version 1
+    let mut stale = q.clone();
+    stale.title = "Lost".to_string();
+    assert!(matches!(
+        store.update_question(&qid("1"), stale).await,
+        Err(Error::EditConflict)
+    ));
+    // Skipping a version is as wrong as repeating one
+    q.version = 4;
+    assert!(matches!(
+        store.update_question(&qid("1"), q).await,
+        Err(Error::EditConflict)
+    ));
+    let stored = store.get_question(&qid("1")).await.unwrap();
+    assert_eq!((stored.title.as_str(), stored.version), ("Edited", 2));
+}
+
+async fn missing_records_are_not_found(store: Store) {
+    let mut q = question("nope");
+    q.version = 2;
+    assert!(matches!(
+        store.update_question(&qid("nope"), q).await,
+        Err(Error::QuestionNotFound)
+    ));
+    assert!(matches!(store.get_question(&qid("nope")).await, Err(Error::QuestionNotFound)));
+    assert!(matches!(store.delete_question(&qid("nope")).await, Err(Error::QuestionNotFound)));
+    assert!(matches!(store.get_answer(&aid("nope")).await, Err(Error::AnswerNotFound)));
+    assert!(matches!(
+        store.update_answer(&aid("nope"), answer("nope", "1")).await,
+        Err(Error::AnswerNotFound)
+    ));
+    assert!(matches!(store.delete_answer(&aid("nope")).await, Err(Error::AnswerNotFound)));
+    assert!(matches!(store.delete_tag("nope").await, Err(Error::TagNotFound)));
+    assert!(matches!(
+        store.get_user(&UserId("nope".to_string())).await,
+        Err(Error::UserNotFound)
+    ));
+    assert!(store.delete_vote("nope").await.unwrap().is_none());
+}
+
+async fn taken_ids_are_refused(store: Store) {
+    store.add_question(question("1")).await.unwrap();
+    assert!(matches!(
+        store.add_question(question("1")).await,
+        Err(Error::QuestionAlreadyExists)
+    ));
+    store.add_answer(answer("a", "1")).await.unwrap();
+    assert!(matches!(
+        store.add_answer(answer("a", "1")).await,
+        Err(Error::AnswerAlreadyExists)
+    ));
+    store.add_user(user("u1", "a@example.com")).await.unwrap();
+    assert!(matches!(
+        store.add_user(user("u2", "a@example.com")).await,
+        Err(Error::EmailTaken)
+    ));
+}
+
+async fn soft_deleted_records_stay_visible(store: Store) {
+    store.add_question(question("1")).await.unwrap();
+    store.add_question(question("2")).await.unwrap();
+    store.add_answer(answer("a", "1")).await.unwrap();
+
+    // Soft deletion is an update that sets `deleted_at`; the store keeps
+    // handing such records out so the trash and restore can see them
+    let mut q = store.get_question(&qid("1")).await.unwrap();
+    q.deleted_at = Some(Utc::now());
+    q.version += 1;
+    store.update_question(&qid("1"), q).await.unwrap();
+    let mut a = store.get_answer(&aid("a")).await.unwrap();
+    a.deleted_at = Some(Utc::now());
+    store.update_answer(&aid("a"), a).await.unwrap();
+
+    let mut questions = store.get_questions().await.unwrap();
+    questions.sort_by(|a, b| a.id.0.cmp(&b.id.0));
+    let deleted: Vec<bool> = questions.iter().map(|q| q.deleted_at.is_some()).collect();
+    assert_eq!(deleted, vec![true, false]);
+    assert!(store.get_question(&qid("1")).await.unwrap().deleted_at.is_some());
+    assert!(store.get_answer(&aid("a")).await.unwrap().deleted_at.is_some());
+    assert_eq!(store.get_answers(&qid("1")).await.unwrap().len(), 1);
+
+    // Hard deletion is what makes them go away
+    store.delete_question(&qid("1")).await.unwrap();
+    assert_eq!(store.delete_answers(&qid("1")).await.unwrap(), 1);
+    assert!(matches!(store.get_question(&qid("1")).await, Err(Error::QuestionNotFound)));
+    assert!(store.get_answers(&qid("1")).await.unwrap().is_empty());
+    assert_eq!(store.get_questions().await.unwrap().len(), 1);
+}
+
+async fn answer_counts_skip_deleted_answers(store: Store) {
+    for id in ["1", "2", "3"] {
+        store.add_question(question(id)).await.unwrap();
+    }
+    for (id, question_id) in [("a", "1"), ("b", "1"), ("c", "1"), ("d", "2")] {
+        store.add_answer(answer(id, question_id)).await.unwrap();
+    }
+    let mut c = store.get_answer(&aid("c")).await.unwrap();
+    c.deleted_at = Some(Utc::now());
+    store.update_answer(&aid("c"), c).await.unwrap();
+    let mut d = store.get_answer(&aid("d")).await.unwrap();
+    d.deleted_at = Some(Utc::now());
+    store.update_answer(&aid("d"), d).await.unwrap();
+
+    let counts = store.answer_counts().await.unwrap();
+    // "2" only has a deleted answer and "3" none, so both are left out
+    assert_eq!(counts, HashMap::from([(qid("1"), 2)]));
+}
+
+async fn scores_add_up_the_votes(store: Store) {
+    store.add_question(question("1")).await.unwrap();
+    store.add_answer(answer("a", "1")).await.unwrap();
+    let q = Revision::question_subject(&qid("1"));
+    let a = Revision::answer_subject(&aid("a"));
+
+    for voter in ["u1", "u2", "u3"] {
+        store.put_vote(vote(q.clone(), voter, Direction::Up)).await.unwrap();
+    }
+    store.put_vote(vote(a.clone(), "u1", Direction::Down)).await.unwrap();
+    // Changing a vote replaces it rather than adding another
+    store.put_vote(vote(q.clone(), "u3", Direction::Down)).await.unwrap();
+
+    assert_eq!(store.get_question(&qid("1")).await.unwrap().score, 1);
+    assert_eq!(store.get_questions().await.unwrap()[0].score, 1);
+    assert_eq!(store.get_answer(&aid("a")).await.unwrap().score, -1);
+    assert_eq!(store.get_answers(&qid("1")).await.unwrap()[0].score, -1);
+
+    let taken_back = store
+        .delete_vote(&Vote::id_of(&q, &UserId("u1".to_string())))
+        .await
+        .unwrap();
+    assert_eq!(taken_back.map(|v| v.direction), Some(Direction::Up));
+    assert_eq!(store.get_question(&qid("1")).await.unwrap().score, 0);
+
+    assert_eq!(store.delete_votes(&a).await.unwrap(), 1);
+    assert_eq!(store.get_answer(&aid("a")).await.unwrap().score, 0);
+}
+
+async fn votes_on_answers_dont_score_questions(store: Store) {
+    // Same id for both, only the subject tells them apart
+    store.add_question(question("1")).await.unwrap();
+    store.add_answer(answer("1", "1")).await.unwrap();
+    let subject = Revision::answer_subject(&aid("1"));
+    store.put_vote(vote(subject, "u1", Direction::Up)).await.unwrap();
+
+    assert_eq!(store.get_question(&qid("1")).await.unwrap().score, 0);
+    assert_eq!(store.get_answer(&aid("1")).await.unwrap().score, 1);
+}
+
+async fn revision_numbers_are_unique(store: Store) {
+    let q = question("1");
+    let first = Revision::of_question(&q, None).numbered(1);
+    store.add_revision(first.clone()).await.unwrap();
+    store
+        .add_revision(Revision::of_question(&q, None).numbered(2))
+        .await
+        .unwrap();
+    assert!(matches!(store.add_revision(first).await, Err(Error::EditConflict)));
+
+    let numbers: Vec<u64> = store
+        .get_revisions(&Revision::question_subject(&q.id))
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| r.number)
+        .collect();
+    assert_eq!(numbers, vec![1, 2]);
+    assert_eq!(store.delete_revisions(&Revision::question_subject(&q.id)).await.unwrap(), 2);
+    assert!(store.get_revisions("questions/1").await.unwrap().is_empty());
+}
+
+async fn users_and_api_keys_are_found_by_email_and_hash(store: Store) {
+    store.add_user(user("u1", "a@example.com")).await.unwrap();
+    assert_eq!(store.get_user_by_email("a@example.com").await.unwrap().id.0, "u1");
+    assert!(matches!(
+        store.get_user_by_email("b@example.com").await,
+        Err(Error::UserNotFound)
+    ));
+
+    let key = ApiKey {
+        id: ApiKeyId("k1".to_string()),
+        name: "bot".to_string(),
+        prefix: "qak_abc".to_string(),
+        hash: "0123abcd".to_string(),
+        scopes: Vec::new(),
+        owner: UserId("u1".to_string()),
+        created_at: Utc::now(),
+        last_used_at: None,
+        revoked_at: None,
+    };
+    store.add_api_key(key.clone()).await.unwrap();
+    assert_eq!(store.get_api_key_by_hash("0123abcd").await.unwrap().id.0, "k1");
+    assert!(matches!(
+        store.get_api_key_by_hash("ffff").await,
+        Err(Error::ApiKeyNotFound)
+    ));
+
+    let mut revoked = key;
+    revoked.revoked_at = Some(Utc::now());
+    store.update_api_key(revoked).await.unwrap();
+    assert!(store.get_api_key(&ApiKeyId("k1".to_string())).await.unwrap().revoked_at.is_some());
+    assert_eq!(store.get_api_keys().await.unwrap().len(), 1);
+}
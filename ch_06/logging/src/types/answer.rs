@@ -1,4 +1,7 @@
-use crate::types::question::QuestionId;
+use crate::types::question::{ID_RULES, QuestionId};
+use crate::types::user::UserId;
+use crate::types::validation::{Rule, Validate, Validator};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
@@ -9,4 +12,40 @@ pub struct Answer {
     pub id: AnswerId,
     pub content: String,
     pub question_id: QuestionId,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    /// Set when the answer, or its question, was soft-deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Account that posted the answer, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<UserId>,
+    /// Upvotes minus downvotes, worked out by the store
+    #[serde(default, skip_deserializing)]
+    pub score: i64,
+}
+
+/// Form body of `POST /answers`. The server picks the id unless one is given.
+#[derive(Deserialize, Debug)]
+pub struct NewAnswer {
+    pub id: Option<AnswerId>,
+    pub content: String,
+    #[serde(rename = "questionId")]
+    pub question_id: QuestionId,
+}
+
+const CONTENT_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(30_000)];
+
+impl Validate for Answer {
+    fn check(&self, validator: &mut Validator) {
+        validator
+            .text("id", &self.id.0, ID_RULES)
+            .text("content", &self.content, CONTENT_RULES);
+    }
+}
+
+impl std::fmt::Display for AnswerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
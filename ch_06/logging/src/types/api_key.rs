@@ -0,0 +1,138 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::types::user::UserId;
+
+/// Prefix of every key, so leaked ones are easy to grep for
+const KEY_PREFIX: &str = "qak_";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ApiKeyId(pub String);
+
+/// What a route lets API keys do. User tokens aren't scoped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "questions:read")]
+    QuestionsRead,
+    #[serde(rename = "questions:write")]
+    QuestionsWrite,
+    #[serde(rename = "answers:read")]
+    AnswersRead,
+    #[serde(rename = "answers:write")]
+    AnswersWrite,
+    #[serde(rename = "tags:write")]
+    TagsWrite,
+    /// Acting on other people's questions and answers, as far as the
+    /// owner's role allows. Without it a key only touches its owner's posts.
+    #[serde(rename = "moderate")]
+    Moderate,
+    /// The trash, roles and API keys themselves
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+/// An API key as it is stored. Only the hash of the secret is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    /// Who or what the key is for, like `release-bot`
+    pub name: String,
+    /// First characters of the key, enough to tell keys apart in a list
+    pub prefix: String,
+    /// Hex SHA-256 of the whole key
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    /// Account the key acts as. Its role still applies on top of the scopes.
+    pub owner: UserId,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// What clients get to see of a key
+#[derive(Debug, Serialize)]
+pub struct ApiKeyView {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub owner: UserId,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Body of `POST /api-keys`
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Defaults to the admin creating the key
+    pub owner: Option<UserId>,
+}
+
+/// Response body of `POST /api-keys`, the only time the key is shown
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    /// Sent as the `X-Api-Key` header
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyView,
+}
+
+/// A fresh random key, with the prefix and hash to store for it
+pub fn generate() -> (String, String, String) {
+    let key = format!(
+        "{}{}",
+        KEY_PREFIX,
+        URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+    );
+    let prefix = key.chars().take(KEY_PREFIX.len() + 6).collect();
+    let hash = hash(&key);
+    (key, prefix, hash)
+}
+
+/// Keys are long and random, so a plain SHA-256 is enough to keep them
+/// from being usable straight out of the database
+pub fn hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+impl From<&ApiKey> for ApiKeyView {
+    fn from(key: &ApiKey) -> Self {
+        ApiKeyView {
+            id: key.id.clone(),
+            name: key.name.clone(),
+            prefix: key.prefix.clone(),
+            scopes: key.scopes.clone(),
+            owner: key.owner.clone(),
+            created_at: key.created_at,
+            last_used_at: key.last_used_at,
+            revoked_at: key.revoked_at,
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Scope::QuestionsRead => "questions:read",
+            Scope::QuestionsWrite => "questions:write",
+            Scope::AnswersRead => "answers:read",
+            Scope::AnswersWrite => "answers:write",
+            Scope::TagsWrite => "tags:write",
+            Scope::Moderate => "moderate",
+            Scope::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for ApiKeyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -1,3 +1,12 @@
 pub mod answer;
+pub mod api_key;
 pub mod pagination;
+pub mod patch;
+pub mod query;
 pub mod question;
+pub mod revision;
+pub mod tag;
+pub mod trash;
+pub mod user;
+pub mod validation;
+pub mod vote;
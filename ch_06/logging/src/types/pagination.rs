@@ -1,74 +1,278 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use handle_errors::Error;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::types::query::{QueryParams, take_one};
+
+/// Page size used when the query doesn't ask for one
+pub const DEFAULT_LIMIT: usize = 20;
+/// Larger `limit`s are capped to this
+pub const MAX_LIMIT: usize = 100;
 
 /// Pagination struct that is getting extracted
 /// from query params
 #[derive(Debug)]
 pub struct Pagination {
-    /// The index of the first item that has to be returned
-    pub start: usize,
-    /// The index of the last item that has to be returned
-    pub end: usize,
+    /// Only items sorting after this cursor are returned
+    pub after: Option<Cursor>,
+    /// The maximum number of items on the page
+    pub limit: usize,
+}
+
+/// Value a listing is sorted by
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SortKey {
+    Number(i64),
+    Text(String),
 }
 
-/// Pagination methods. These two were my idea!
-impl Pagination {
-    pub fn sanitize(mut self) -> Self {
-        if self.start > self.end {
-            std::mem::swap(&mut self.start, &mut self.end);
-            // let saved_start = self.start;
-            // self.start = self.end;
-            // self.end = saved_start;
+/// Position in a listing. Clients only ever see it as the opaque string
+/// from `Cursor::encode`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Sort key of the last item on the previous page
+    pub key: SortKey,
+    /// Its id, which breaks ties between equal keys
+    pub id: String,
+}
+
+/// Direction of a sorted listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Order {
+    /// Parses the `order` query parameter, `default` if it is absent
+    pub fn parse(value: Option<String>, default: Order) -> Result<Order, Error> {
+        match value.as_deref() {
+            None => Ok(default),
+            Some("asc") => Ok(Order::Asc),
+            Some("desc") => Ok(Order::Desc),
+            Some(other) => Err(Error::InvalidParameter(format!(
+                "order must be `asc` or `desc`, not `{}`",
+                other
+            ))),
         }
-        self
     }
-    pub fn saturate(mut self, max_len: usize) -> Self {
-        if self.end > max_len {
-            println!("Saturating! Level: {}", max_len);
-            self.end = max_len;
-            println!("Start: {} End: {}", self.start, self.end);
-        }
-        self
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("a cursor always serializes");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(token: &str) -> Result<Self, Error> {
+        let json = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| Error::InvalidCursor)?;
+        serde_json::from_slice(&json).map_err(|_| Error::InvalidCursor)
     }
 }
 
-/// Extract query parameters from the `/questions` route
+/// One page of a listing
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass as `after` to get the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+    /// Number of items in the whole listing
+    pub total: usize,
+}
+
+/// Takes the pagination parameters out of a listing's query
 /// # Example query
-/// GET requests to this route can have a pagination attached so we just
-/// return the questions we need
-/// `\questions?start=1&end=10`
+/// GET requests to listing routes can have a pagination attached so we just
+/// return the items we need
+/// `/questions?after=<next_cursor of the previous page>&limit=10`
 /// # Example usage
 /// ```rust
-/// let mut query = HashMap::new();
-/// query.insert("start".to_string(), "1".to_string());
-/// query.insert("end".to_string(), "10".to_string());
-/// let p = types::pagination::extract_pagination(query).unwrap();
-/// assert_eq!(p.start, 1);
-/// assert_eq!(p.end, 10);
+/// let mut query = vec![("limit".to_string(), "10".to_string())];
+/// let p = types::pagination::extract_pagination(&mut query).unwrap();
+/// assert!(p.after.is_none());
+/// assert_eq!(p.limit, 10);
+/// assert!(query.is_empty());
 /// ```
-pub fn extract_pagination(params: HashMap<String, String>) -> Result<Pagination, Error> {
-    // Could be improved in the future
-    if params.contains_key("start") && params.contains_key("end") {
-        let mut pagination = Pagination {
-            // Takes the "start" parameter in the query
-            // and tries to convert it to a number
-            start: params
-                .get("start")
-                .unwrap()
-                .parse::<usize>()
-                .map_err(Error::ParseError)?,
-            // Takes the "end" parameter in the query
-            // and tries to convert it to a number
-            end: params
-                .get("end")
-                .unwrap()
-                .parse::<usize>()
-                .map_err(Error::ParseError)?,
+pub fn extract_pagination(params: &mut QueryParams) -> Result<Pagination, Error> {
+    let after = match take_one(params, "after")? {
+        Some(token) => Some(Cursor::decode(&token)?),
+        None => None,
+    };
+    let limit = match take_one(params, "limit")? {
+        // Takes the "limit" parameter in the query
+        // and tries to convert it to a number
+        Some(limit) => limit.parse::<usize>().map_err(Error::ParseError)?,
+        None => DEFAULT_LIMIT,
+    };
+    if limit == 0 {
+        return Err(Error::InvalidParameter(
+            "limit must be at least 1".to_string(),
+        ));
+    }
+
+    Ok(Pagination {
+        after,
+        limit: limit.min(MAX_LIMIT),
+    })
+}
+
+/// Sorts `items` by their `position` in `order` and cuts one page out of
+/// them. Ties on the sort key are broken by id, so the order is stable.
+pub fn paginate<T>(
+    items: Vec<T>,
+    pagination: &Pagination,
+    order: Order,
+    position: impl Fn(&T) -> Cursor,
+) -> Page<T> {
+    let total = items.len();
+    let mut items: Vec<(Cursor, T)> = items
+        .into_iter()
+        .map(|item| (position(&item), item))
+        .collect();
+    items.sort_by(|(a, _), (b, _)| match order {
+        Order::Asc => a.cmp(b),
+        Order::Desc => b.cmp(a),
+    });
+
+    let mut rest = items.into_iter().filter(|(at, _)| match &pagination.after {
+        Some(after) => match order {
+            Order::Asc => at > after,
+            Order::Desc => at < after,
+        },
+        None => true,
+    });
+
+    let page: Vec<(Cursor, T)> = rest.by_ref().take(pagination.limit).collect();
+    let next_cursor = match (page.last(), rest.next()) {
+        (Some((last, _)), Some(_)) => Some(last.encode()),
+        _ => None,
+    };
+
+    Page {
+        items: page.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(&(key, id): &(i64, &str)) -> Cursor {
+        Cursor {
+            key: SortKey::Number(key),
+            id: id.to_string(),
+        }
+    }
+
+    fn pagination(after: Option<String>, limit: usize) -> Pagination {
+        Pagination {
+            after: after.map(|token| Cursor::decode(&token).unwrap()),
+            limit,
+        }
+    }
+
+    fn ids(page: &Page<(i64, &str)>) -> Vec<String> {
+        page.items.iter().map(|(_, id)| id.to_string()).collect()
+    }
+
+    /// Follows `next_cursor` until the last page
+    fn walk(items: &[(i64, &'static str)], limit: usize, order: Order) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut after = None;
+        loop {
+            let page = paginate(items.to_vec(), &pagination(after, limit), order, at);
+            assert_eq!(page.total, items.len());
+            pages.push(ids(&page));
+            match page.next_cursor {
+                Some(next) => after = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn pages_through_in_ascending_order() {
+        let items = [(3, "c"), (1, "a"), (5, "e"), (2, "b"), (4, "d")];
+        assert_eq!(
+            walk(&items, 2, Order::Asc),
+            vec![vec!["a", "b"], vec!["c", "d"], vec!["e"]]
+        );
+    }
+
+    #[test]
+    fn pages_through_in_descending_order() {
+        let items = [(3, "c"), (1, "a"), (5, "e"), (2, "b"), (4, "d")];
+        assert_eq!(
+            walk(&items, 2, Order::Desc),
+            vec![vec!["e", "d"], vec!["c", "b"], vec!["a"]]
+        );
+    }
+
+    #[test]
+    fn a_full_last_page_has_no_next_cursor() {
+        let items = [(1, "a"), (2, "b"), (3, "c"), (4, "d")];
+        assert_eq!(
+            walk(&items, 2, Order::Asc),
+            vec![vec!["a", "b"], vec!["c", "d"]]
+        );
+        assert_eq!(walk(&items, 4, Order::Asc), vec![vec!["a", "b", "c", "d"]]);
+    }
+
+    #[test]
+    fn empty_listing_is_one_empty_page() {
+        let page = paginate(Vec::new(), &pagination(None, 10), Order::Asc, at);
+        assert!(page.items.is_empty());
+        assert_eq!(page.next_cursor, None);
+        assert_eq!(page.total, 0);
+    }
+
+    #[test]
+    fn ties_are_broken_by_id_without_skipping_or_repeating() {
+        let items = [(1, "d"), (1, "b"), (0, "z"), (1, "a"), (1, "c")];
+        assert_eq!(
+            walk(&items, 2, Order::Asc),
+            vec![vec!["z", "a"], vec!["b", "c"], vec!["d"]]
+        );
+        assert_eq!(
+            walk(&items, 2, Order::Desc),
+            vec![vec!["d", "c"], vec!["b", "a"], vec!["z"]]
+        );
+    }
+
+    #[test]
+    fn cursor_of_a_removed_item_still_finds_its_place() {
+        let first = paginate(
+            vec![(1, "a"), (2, "b"), (3, "c")],
+            &pagination(None, 2),
+            Order::Asc,
+            at,
+        );
+        // "b", the last item seen, is gone by the time the next page is asked for
+        let rest = paginate(
+            vec![(1, "a"), (3, "c")],
+            &pagination(first.next_cursor, 2),
+            Order::Asc,
+            at,
+        );
+        assert_eq!(ids(&rest), vec!["c"]);
+        assert_eq!(rest.next_cursor, None);
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            key: SortKey::Text("héllo".to_string()),
+            id: "42".to_string(),
         };
-        // println!("{:?}", pagination);
-        pagination = pagination.sanitize();
-        // println!("{:?}", pagination);
-        return Ok(pagination);
+        assert_eq!(Cursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(matches!(
+            Cursor::decode("not a cursor"),
+            Err(Error::InvalidCursor)
+        ));
     }
-    Err(Error::MissingParameters)
 }
@@ -0,0 +1,92 @@
+use serde_json::{Map, Value};
+
+/// Applies an RFC 7396 JSON Merge Patch to `target`: members of `patch`
+/// replace those of `target`, objects are merged recursively and `null`
+/// removes a member.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge(target.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn merged(target: Value, patch: Value) -> Value {
+        let mut target = target;
+        merge(&mut target, &patch);
+        target
+    }
+
+    /// The examples of RFC 7396, Appendix A
+    #[test]
+    fn rfc_7396_examples() {
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (target, patch, expected) in cases {
+            assert_eq!(
+                merged(target.clone(), patch.clone()),
+                expected,
+                "{} patched with {}",
+                target,
+                patch
+            );
+        }
+    }
+
+    #[test]
+    fn empty_patch_changes_nothing() {
+        let target = json!({"title": "t", "tags": ["a"]});
+        assert_eq!(merged(target.clone(), json!({})), target);
+    }
+
+    #[test]
+    fn removing_a_missing_member_is_a_no_op() {
+        assert_eq!(merged(json!({"a": 1}), json!({"b": null})), json!({"a": 1}));
+    }
+
+    #[test]
+    fn nested_objects_merge_without_losing_siblings() {
+        assert_eq!(
+            merged(
+                json!({"a": {"x": 1, "y": {"z": 2, "w": 3}}}),
+                json!({"a": {"y": {"z": null, "v": 4}}})
+            ),
+            json!({"a": {"x": 1, "y": {"w": 3, "v": 4}}})
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use handle_errors::Error;
+
+use crate::types::answer::{Answer, AnswerId};
+use crate::types::pagination::{Cursor, Order, Pagination, SortKey, extract_pagination};
+use crate::types::question::Question;
+use crate::types::tag::TagCatalog;
+
+/// Query string as `warp::query` hands it over: every pair in the order
+/// given, so repeated parameters like `tag` survive
+pub type QueryParams = Vec<(String, String)>;
+
+/// Removes `key` from `params` and returns its value. A parameter that
+/// should appear once but was given twice is an error.
+pub fn take_one(params: &mut QueryParams, key: &str) -> Result<Option<String>, Error> {
+    let mut values = take_all(params, key);
+    match values.len() {
+        0 => Ok(None),
+        1 => Ok(values.pop()),
+        _ => Err(Error::InvalidParameter(format!(
+            "{} can only be given once",
+            key
+        ))),
+    }
+}
+
+/// Removes every occurrence of `key` from `params` and returns the values
+pub fn take_all(params: &mut QueryParams, key: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    params.retain(|(k, v)| {
+        if k == key {
+            values.push(v.clone());
+            false
+        } else {
+            true
+        }
+    });
+    values
+}
+
+/// Called once a route took every parameter it knows about
+pub fn reject_unknown(params: QueryParams) -> Result<(), Error> {
+    match params.into_iter().next() {
+        Some((key, _)) => Err(Error::UnknownParameter(key)),
+        None => Ok(()),
+    }
+}
+
+/// Whether a question needs any or all of the requested tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMode {
+    Any,
+    All,
+}
+
+/// Which questions `answered` lets through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answered {
+    /// At least one answer
+    Yes,
+    No,
+    /// An accepted answer
+    Accepted,
+}
+
+/// What `GET /questions` can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuestionSort {
+    Created,
+    Updated,
+    Title,
+    Answers,
+    Score,
+}
+
+/// Query parameters of `GET /questions`
+/// # Example query
+/// `/questions?tag=rust&tag=warp&tag_mode=all&q=lifetime&answered=false&sort=title&order=asc&limit=10`
+#[derive(Debug)]
+pub struct QuestionQuery {
+    /// `tag`, repeatable. Aliases match the tag they stand for.
+    pub tags: Vec<String>,
+    /// `tag_mode`: `any` (default) or `all`
+    pub tag_mode: TagMode,
+    /// `q`: substring of the title or the content, ignoring case
+    pub text: Option<String>,
+    /// `answered`: `true` for questions with answers, `false` for those
+    /// without, `accepted` for those with an accepted answer
+    pub answered: Option<Answered>,
+    /// `sort`: `created` (default), `updated`, `title`, `answers` or
+    /// `score`, also spelled `votes`
+    pub sort: QuestionSort,
+    /// `order`: `asc` or `desc`. Titles default to `asc`, everything else
+    /// to `desc`, newest, most answered or best scored first.
+    pub order: Order,
+    /// `after` and `limit`
+    pub pagination: Pagination,
+}
+
+impl QuestionQuery {
+    /// Whether `question`, which has `answers` answers, passes the filters.
+    /// Its tags are resolved through `catalog`, so questions tagged before
+    /// an alias was added still match.
+    pub fn matches(&self, question: &Question, answers: usize, catalog: &TagCatalog) -> bool {
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = question
+                .tags
+                .iter()
+                .flatten()
+                .map(|tag| catalog.resolve(tag))
+                .collect();
+            let has = |wanted: &String| tags.contains(wanted);
+            let tagged = match self.tag_mode {
+                TagMode::Any => self.tags.iter().any(has),
+                TagMode::All => self.tags.iter().all(has),
+            };
+            if !tagged {
+                return false;
+            }
+        }
+
+        if let Some(text) = &self.text {
+            let text = text.to_lowercase();
+            if !question.title.to_lowercase().contains(&text)
+                && !question.content.to_lowercase().contains(&text)
+            {
+                return false;
+            }
+        }
+
+        match self.answered {
+            Some(Answered::Yes) => answers > 0,
+            Some(Answered::No) => answers == 0,
+            Some(Answered::Accepted) => question.accepted_answer.is_some(),
+            None => true,
+        }
+    }
+
+    /// Where `question`, which has `answers` answers, sorts in the listing
+    pub fn position(&self, question: &Question, answers: usize) -> Cursor {
+        let key = match self.sort {
+            QuestionSort::Created => SortKey::Number(question.created_at.timestamp_micros()),
+            QuestionSort::Updated => SortKey::Number(question.updated_at.timestamp_micros()),
+            QuestionSort::Title => SortKey::Text(question.title.to_lowercase()),
+            QuestionSort::Answers => SortKey::Number(answers as i64),
+            QuestionSort::Score => SortKey::Number(question.score),
+        };
+        Cursor {
+            key,
+            id: question.id.0.clone(),
+        }
+    }
+}
+
+pub fn extract_question_query(mut params: QueryParams) -> Result<QuestionQuery, Error> {
+    let tags = take_all(&mut params, "tag");
+    let tag_mode = match take_one(&mut params, "tag_mode")?.as_deref() {
+        None | Some("any") => TagMode::Any,
+        Some("all") => TagMode::All,
+        Some(other) => {
+            return Err(Error::InvalidParameter(format!(
+                "tag_mode must be `any` or `all`, not `{}`",
+                other
+            )));
+        }
+    };
+    let text = take_one(&mut params, "q")?.filter(|q| !q.trim().is_empty());
+    let answered = match take_one(&mut params, "answered")?.as_deref() {
+        None => None,
+        Some("true") => Some(Answered::Yes),
+        Some("false") => Some(Answered::No),
+        Some("accepted") => Some(Answered::Accepted),
+        Some(other) => {
+            return Err(Error::InvalidParameter(format!(
+                "answered must be `true`, `false` or `accepted`, not `{}`",
+                other
+            )));
+        }
+    };
+    let sort = match take_one(&mut params, "sort")?.as_deref() {
+        None | Some("created") => QuestionSort::Created,
+        Some("updated") => QuestionSort::Updated,
+        Some("title") => QuestionSort::Title,
+        Some("answers") => QuestionSort::Answers,
+        Some("score") | Some("votes") => QuestionSort::Score,
+        Some(other) => {
+            return Err(Error::InvalidParameter(format!(
+                "sort must be `created`, `updated`, `title`, `answers`, `score` or `votes`, not `{}`",
+                other
+            )));
+        }
+    };
+    let default_order = match sort {
+        QuestionSort::Title => Order::Asc,
+        _ => Order::Desc,
+    };
+    let order = Order::parse(take_one(&mut params, "order")?, default_order)?;
+    let pagination = extract_pagination(&mut params)?;
+    reject_unknown(params)?;
+
+    Ok(QuestionQuery {
+        tags,
+        tag_mode,
+        text,
+        answered,
+        sort,
+        order,
+        pagination,
+    })
+}
+
+/// What answer listings can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnswerSort {
+    Created,
+    Score,
+}
+
+/// Query parameters of `GET /questions/{id}/answers`
+/// # Example query
+/// `/questions/1/answers?sort=score&limit=10`
+#[derive(Debug)]
+pub struct AnswerQuery {
+    /// `sort`: `created` (default) or `score`, also spelled `votes`
+    pub sort: AnswerSort,
+    /// `order`: `asc` or `desc`. By creation, answers default to `asc`,
+    /// oldest first like a conversation, by score to `desc`.
+    pub order: Order,
+    /// `after` and `limit`
+    pub pagination: Pagination,
+}
+
+impl AnswerQuery {
+    /// Where `answer` sorts in the listing. The `accepted` answer gets the
+    /// key that comes first in either order, so it leads the first page.
+    pub fn position(&self, answer: &Answer, accepted: Option<&AnswerId>) -> Cursor {
+        let key = if accepted == Some(&answer.id) {
+            match self.order {
+                Order::Asc => SortKey::Number(i64::MIN),
+                Order::Desc => SortKey::Number(i64::MAX),
+            }
+        } else {
+            match self.sort {
+                AnswerSort::Created => SortKey::Number(answer.created_at.timestamp_micros()),
+                AnswerSort::Score => SortKey::Number(answer.score),
+            }
+        };
+        Cursor {
+            key,
+            id: answer.id.0.clone(),
+        }
+    }
+}
+
+pub fn extract_answer_query(mut params: QueryParams) -> Result<AnswerQuery, Error> {
+    let sort = match take_one(&mut params, "sort")?.as_deref() {
+        None | Some("created") => AnswerSort::Created,
+        Some("score") | Some("votes") => AnswerSort::Score,
+        Some(other) => {
+            return Err(Error::InvalidParameter(format!(
+                "sort must be `created`, `score` or `votes`, not `{}`",
+                other
+            )));
+        }
+    };
+    let default_order = match sort {
+        AnswerSort::Created => Order::Asc,
+        AnswerSort::Score => Order::Desc,
+    };
+    let order = Order::parse(take_one(&mut params, "order")?, default_order)?;
+    let pagination = extract_pagination(&mut params)?;
+    reject_unknown(params)?;
+
+    Ok(AnswerQuery {
+        sort,
+        order,
+        pagination,
+    })
+}
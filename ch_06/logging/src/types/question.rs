@@ -1,16 +1,80 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::types::answer::AnswerId;
+use crate::types::tag::TAG_RULES;
+use crate::types::user::UserId;
+use crate::types::validation::{Rule, Validate, Validator};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Question {
     pub id: QuestionId,
     pub title: String,
     pub content: String,
     pub tags: Option<Vec<String>>,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+    /// Set when the question was soft-deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// Bumped by every write, sent in the `ETag` with the score
+    #[serde(default = "first_version")]
+    pub version: u64,
+    /// Account that asked the question. Questions from before accounts
+    /// existed have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<UserId>,
+    /// The answer the author marked as the one that solved it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accepted_answer: Option<AnswerId>,
+    /// Upvotes minus downvotes. The store works it out from the votes,
+    /// and votes don't bump `version`, as they aren't edits.
+    #[serde(default, skip_deserializing)]
+    pub score: i64,
+}
+
+fn first_version() -> u64 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct QuestionId(pub String);
 
+/// Body of `POST /questions`. The server picks the id unless one is given.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewQuestion {
+    pub id: Option<QuestionId>,
+    pub title: String,
+    pub content: String,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Ids end up in paths and in the keys of revisions and votes, so they
+/// are kept to characters that need no escaping there
+pub const ID_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(64),
+    Rule::Charset {
+        allowed: |c| c.is_ascii_alphanumeric() || c == '-' || c == '_',
+        description: "letters, digits, `-` and `_`",
+    },
+];
+const TITLE_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(200)];
+const CONTENT_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(30_000)];
+const TAGS_RULES: &[Rule] = &[Rule::MaxItems(5)];
+
+impl Validate for Question {
+    fn check(&self, validator: &mut Validator) {
+        validator
+            .text("id", &self.id.0, ID_RULES)
+            .text("title", &self.title, TITLE_RULES)
+            .text("content", &self.content, CONTENT_RULES)
+            .list("tags", self.tags.as_deref().unwrap_or_default(), TAGS_RULES, TAG_RULES);
+    }
+}
+
 impl std::fmt::Display for Question {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -26,3 +90,25 @@ impl std::fmt::Display for QuestionId {
         write!(f, "{}", self.0)
     }
 }
+
+/// What `DELETE /questions/{id}` and `DELETE /answers/{id}` do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletePolicy {
+    /// Refuse to delete a question that still has answers
+    Refuse,
+    /// Delete the question together with its answers
+    Cascade,
+    /// Only mark the question and its answers as deleted, so they can be
+    /// restored until they get purged
+    Soft,
+}
+
+/// Response body of `DELETE /questions/{id}`
+#[derive(Debug, Serialize)]
+pub struct DeletedQuestion {
+    pub id: QuestionId,
+    pub policy: DeletePolicy,
+    /// Answers that were deleted or hidden along with the question
+    pub answers_affected: usize,
+}
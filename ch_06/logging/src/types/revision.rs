@@ -0,0 +1,377 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{
+    answer::{Answer, AnswerId},
+    question::{Question, QuestionId},
+    user::UserId,
+};
+
+/// State of a question or an answer after one edit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    /// `{subject}/revisions/{number}`, unique across all revisions
+    pub id: String,
+    /// `questions/{id}` or `answers/{id}`
+    pub subject: String,
+    /// 1 for the state the record was created with, counting up from there
+    pub number: u64,
+    pub created_at: DateTime<Utc>,
+    /// Who made the edit, if it was made by someone signed in
+    pub author: Option<UserId>,
+    /// Questions only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub content: String,
+    /// Questions only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    /// Set when the edit rolled back to the revision with this number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback_of: Option<u64>,
+    /// What the edit changed, worked out once when the revision is
+    /// recorded. Revisions recorded before that have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Changes>,
+}
+
+impl Revision {
+    pub fn question_subject(id: &QuestionId) -> String {
+        format!("questions/{}", id)
+    }
+
+    pub fn answer_subject(id: &AnswerId) -> String {
+        format!("answers/{}", id)
+    }
+
+    /// Revision with the current state of `question`, still to be numbered
+    pub fn of_question(question: &Question, author: Option<UserId>) -> Self {
+        Revision::new(
+            Revision::question_subject(&question.id),
+            author,
+            Some(question.title.clone()),
+            question.content.clone(),
+            question.tags.clone(),
+        )
+    }
+
+    /// Revision with the current state of `answer`, still to be numbered
+    pub fn of_answer(answer: &Answer, author: Option<UserId>) -> Self {
+        Revision::new(
+            Revision::answer_subject(&answer.id),
+            author,
+            None,
+            answer.content.clone(),
+            None,
+        )
+    }
+
+    pub fn numbered(mut self, number: u64) -> Self {
+        self.id = format!("{}/revisions/{}", self.subject, number);
+        self.number = number;
+        self
+    }
+
+    fn new(
+        subject: String,
+        author: Option<UserId>,
+        title: Option<String>,
+        content: String,
+        tags: Option<Vec<String>>,
+    ) -> Self {
+        Revision {
+            id: String::new(),
+            subject,
+            number: 0,
+            created_at: Utc::now(),
+            author,
+            title,
+            content,
+            tags,
+            rollback_of: None,
+            changes: None,
+        }
+    }
+}
+
+/// A revision as the API shows it, with what it changed
+#[derive(Debug, Serialize)]
+pub struct RevisionView {
+    #[serde(flatten)]
+    pub revision: Revision,
+    /// Changes from the previous revision, everything for the first one
+    pub changes: Changes,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Changes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<TextChange>,
+    /// Changed lines of the content
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content: Vec<LineChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<TagChanges>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextChange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineOp {
+    Delete,
+    Insert,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineChange {
+    pub op: LineOp,
+    /// 1-based line number, in the old text for deletions and in the new
+    /// one for insertions
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl RevisionView {
+    /// Shows `revision` as an edit of `previous`. The changes recorded with
+    /// it are used when there are any.
+    pub fn new(mut revision: Revision, previous: Option<&Revision>) -> Self {
+        let changes = match revision.changes.take() {
+            Some(changes) => changes,
+            None => Changes::between(previous, &revision),
+        };
+        RevisionView { revision, changes }
+    }
+}
+
+impl Changes {
+    /// What `revision` changed compared to `previous`, everything if there
+    /// is no previous revision
+    pub fn between(previous: Option<&Revision>, revision: &Revision) -> Self {
+        let title = previous.and_then(|p| p.title.clone());
+        let content = previous.map(|p| p.content.as_str()).unwrap_or("");
+        let tags = previous.and_then(|p| p.tags.clone()).unwrap_or_default();
+
+        let new_tags = revision.tags.clone().unwrap_or_default();
+        let added: Vec<String> = new_tags
+            .iter()
+            .filter(|t| !tags.contains(t))
+            .cloned()
+            .collect();
+        let removed: Vec<String> = tags
+            .iter()
+            .filter(|t| !new_tags.contains(t))
+            .cloned()
+            .collect();
+
+        Changes {
+            title: (title != revision.title).then(|| TextChange {
+                from: title,
+                to: revision.title.clone(),
+            }),
+            content: diff_lines(content, &revision.content),
+            tags: (!added.is_empty() || !removed.is_empty())
+                .then_some(TagChanges { added, removed }),
+        }
+    }
+}
+
+/// Largest LCS table `diff_lines` builds, in cells. Past it the changed
+/// lines are reported as all deleted and all inserted.
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+/// Line diff of `old` and `new` by longest common subsequence, listing only
+/// the lines that were deleted or inserted. Lines the two share at the start
+/// and the end are left out of the table, so a small edit in a long text
+/// stays cheap.
+pub fn diff_lines(old: &str, new: &str) -> Vec<LineChange> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let delete = |i: usize| LineChange {
+        op: LineOp::Delete,
+        line: prefix + i + 1,
+        text: old_mid[i].to_string(),
+    };
+    let insert = |j: usize| LineChange {
+        op: LineOp::Insert,
+        line: prefix + j + 1,
+        text: new_mid[j].to_string(),
+    };
+
+    let cells = (old_mid.len() + 1).saturating_mul(new_mid.len() + 1);
+    if cells > MAX_DIFF_CELLS {
+        return (0..old_mid.len())
+            .map(delete)
+            .chain((0..new_mid.len()).map(insert))
+            .collect();
+    }
+
+    // common[i][j]: length of the LCS of old_mid[i..] and new_mid[j..]
+    let width = new_mid.len() + 1;
+    let mut common = vec![0u32; cells];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            common[i * width + j] = if old_mid[i] == new_mid[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            i += 1;
+            j += 1;
+        } else if i < old_mid.len()
+            && (j == new_mid.len() || common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            changes.push(delete(i));
+            i += 1;
+        } else {
+            changes.push(insert(j));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(op: LineOp, line: usize, text: &str) -> LineChange {
+        LineChange {
+            op,
+            line,
+            text: text.to_string(),
+        }
+    }
+
+    /// Rebuilds the new text from `old` and its diff, to check a diff is
+    /// complete without fixing which of several minimal diffs it picked
+    fn apply(old: &str, changes: &[LineChange]) -> String {
+        let deleted: Vec<usize> = changes
+            .iter()
+            .filter(|c| c.op == LineOp::Delete)
+            .map(|c| c.line)
+            .collect();
+        let mut lines: Vec<&str> = old
+            .lines()
+            .enumerate()
+            .filter(|(i, _)| !deleted.contains(&(i + 1)))
+            .map(|(_, line)| line)
+            .collect();
+        for c in changes.iter().filter(|c| c.op == LineOp::Insert) {
+            lines.insert(c.line - 1, &c.text);
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn same_text_has_no_changes() {
+        assert!(diff_lines("a\nb\nc", "a\nb\nc").is_empty());
+        assert!(diff_lines("", "").is_empty());
+    }
+
+    #[test]
+    fn from_and_to_nothing() {
+        assert_eq!(
+            diff_lines("", "a\nb"),
+            vec![change(LineOp::Insert, 1, "a"), change(LineOp::Insert, 2, "b")]
+        );
+        assert_eq!(
+            diff_lines("a\nb", ""),
+            vec![change(LineOp::Delete, 1, "a"), change(LineOp::Delete, 2, "b")]
+        );
+    }
+
+    #[test]
+    fn line_numbers_count_the_shared_prefix() {
+        assert_eq!(
+            diff_lines("a\nb\nc\nd", "a\nb\nx\nd"),
+            vec![change(LineOp::Delete, 3, "c"), change(LineOp::Insert, 3, "x")]
+        );
+    }
+
+    #[test]
+    fn insertions_use_new_line_numbers_and_deletions_old_ones() {
+        assert_eq!(
+            diff_lines("a\nb\nc", "x\na\nc\ny"),
+            vec![
+                change(LineOp::Insert, 1, "x"),
+                change(LineOp::Delete, 2, "b"),
+                change(LineOp::Insert, 4, "y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_longest_common_subsequence() {
+        let old = "a\nb\nc\nd\ne\nf";
+        let new = "b\nx\nd\ne\ny\nf\nz";
+        let changes = diff_lines(old, new);
+        // "b", "d", "e" and "f" stay, everything else changes
+        assert_eq!(changes.len(), 2 + 3);
+        assert_eq!(apply(old, &changes), new);
+    }
+
+    #[test]
+    fn repeated_lines_are_matched_once_each() {
+        let old = "x\nx\nx";
+        let new = "x\nx";
+        assert_eq!(diff_lines(old, new), vec![change(LineOp::Delete, 3, "x")]);
+        assert_eq!(apply("x", &diff_lines("x", "x\nx\nx")), "x\nx\nx");
+    }
+
+    #[test]
+    fn small_edit_in_a_long_text() {
+        let old: Vec<String> = (0..20_000).map(|i| format!("line {}", i)).collect();
+        let mut new = old.clone();
+        new[10_000] = "changed".to_string();
+        let changes = diff_lines(&old.join("\n"), &new.join("\n"));
+        assert_eq!(
+            changes,
+            vec![
+                change(LineOp::Delete, 10_001, "line 10000"),
+                change(LineOp::Insert, 10_001, "changed"),
+            ]
+        );
+    }
+
+    #[test]
+    fn too_large_a_table_replaces_the_whole_middle() {
+        // 3001 * 3001 cells, past MAX_DIFF_CELLS
+        let old: Vec<String> = (0..3_000).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..3_000).map(|i| format!("new {}", i)).collect();
+        let old = format!("top\n{}\nbottom", old.join("\n"));
+        let new = format!("top\n{}\nbottom", new.join("\n"));
+        let changes = diff_lines(&old, &new);
+        assert_eq!(changes.len(), 6_000);
+        assert_eq!(changes[0], change(LineOp::Delete, 2, "old 0"));
+        assert_eq!(changes[3_000], change(LineOp::Insert, 2, "new 0"));
+        assert_eq!(apply(&old, &changes), new);
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::types::validation::{Rule, Validator};
+
+/// Rules every tag name meets. They are checked after normalization, so
+/// the name is already lowercase.
+pub const TAG_RULES: &[Rule] = &[
+    Rule::NotBlank,
+    Rule::MaxLength(35),
+    Rule::Charset {
+        allowed: |c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-+#.".contains(c),
+        description: "lowercase letters, digits, `-`, `+`, `#` and `.`",
+    },
+];
+
+/// Entry of the tag catalog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    /// Normalized name, the one questions end up tagged with
+    pub name: String,
+    pub description: Option<String>,
+    /// Other names that get rewritten to `name`, normalized as well
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// Body of `PUT /tags/{name}`
+#[derive(Debug, Deserialize)]
+pub struct TagBody {
+    pub description: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// Body of `POST /tags/{name}/merge`
+#[derive(Debug, Deserialize)]
+pub struct TagMerge {
+    /// Tag the questions get instead. If it isn't in the catalog yet, the
+    /// merge renames the tag.
+    pub into: String,
+}
+
+/// Response body of `POST /tags/{name}/merge`
+#[derive(Debug, Serialize)]
+pub struct MergedTag {
+    pub from: String,
+    pub into: Tag,
+    pub questions_affected: usize,
+}
+
+/// A tag as `GET /tags` lists it
+#[derive(Debug, Clone, Serialize)]
+pub struct TagSummary {
+    pub name: String,
+    pub description: Option<String>,
+    pub aliases: Vec<String>,
+    /// Number of live questions tagged with it
+    pub count: usize,
+    /// Whether the tag is in the catalog or only used by questions
+    pub known: bool,
+}
+
+/// Whether questions may use tags that aren't in the catalog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagPolicy {
+    /// Any tag goes, unknown ones are only normalized
+    Open,
+    /// Unknown tags are rejected
+    Strict,
+}
+
+/// Lowercases `raw`, trims it and joins its words with dashes, so
+/// " Rust  Lang" and "rust_lang" both become "rust-lang"
+pub fn normalize(raw: &str) -> String {
+    raw.to_lowercase()
+        .split(|c: char| c.is_whitespace() || c == '_' || c == '-')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The catalog in the shape tag resolution needs
+#[derive(Debug, Default)]
+pub struct TagCatalog {
+    tags: HashMap<String, Tag>,
+    /// alias -> name of the tag it stands for
+    aliases: HashMap<String, String>,
+}
+
+impl TagCatalog {
+    pub fn new(tags: Vec<Tag>) -> Self {
+        let mut catalog = TagCatalog::default();
+        for tag in tags {
+            for alias in &tag.aliases {
+                catalog.aliases.insert(alias.clone(), tag.name.clone());
+            }
+            catalog.tags.insert(tag.name.clone(), tag);
+        }
+        catalog
+    }
+
+    /// Normalizes `raw` and follows it to the tag it is an alias of
+    pub fn resolve(&self, raw: &str) -> String {
+        let name = normalize(raw);
+        match self.aliases.get(&name) {
+            Some(canonical) => canonical.clone(),
+            None => name,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        self.tags.get(name)
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &Tag> {
+        self.tags.values()
+    }
+
+    /// Name of the tag that has `alias` as one of its aliases
+    pub fn alias_of(&self, alias: &str) -> Option<&str> {
+        self.aliases.get(alias).map(String::as_str)
+    }
+
+    /// Resolves the tags of a question being written, dropping blank ones
+    /// and duplicates
+    pub fn apply(&self, tags: Option<Vec<String>>) -> Option<Vec<String>> {
+        let tags = tags?;
+        let mut resolved: Vec<String> = Vec::with_capacity(tags.len());
+        for tag in tags {
+            let tag = self.resolve(&tag);
+            if !tag.is_empty() && !resolved.contains(&tag) {
+                resolved.push(tag);
+            }
+        }
+        Some(resolved)
+    }
+
+    /// Under `TagPolicy::Strict`, records every one of the resolved `tags`
+    /// that isn't in the catalog, next to the payload's other broken rules
+    pub fn check(&self, tags: &[String], policy: TagPolicy, validator: &mut Validator) {
+        if policy != TagPolicy::Strict {
+            return;
+        }
+        for tag in tags.iter().filter(|tag| !self.tags.contains_key(*tag)) {
+            validator.fail(
+                "tags".to_string(),
+                "unknown_tag",
+                format!("`{}` isn't in the tag catalog", tag),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> TagCatalog {
+        TagCatalog::new(vec![Tag {
+            name: "rust".to_string(),
+            description: None,
+            aliases: vec!["rust-lang".to_string()],
+        }])
+    }
+
+    fn unknown(tags: &[&str], policy: TagPolicy) -> Vec<String> {
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        let mut validator = Validator::new();
+        catalog().check(&tags, policy, &mut validator);
+        match validator.finish() {
+            Ok(()) => Vec::new(),
+            Err(handle_errors::Error::ValidationFailed(errors)) => {
+                errors.into_iter().map(|e| e.message).collect()
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn apply_resolves_aliases_and_drops_blanks_and_duplicates() {
+        let tags = vec![" Rust_Lang", "rust", "", "  ", "Warp", "warp"];
+        let tags = tags.into_iter().map(String::from).collect();
+        assert_eq!(
+            catalog().apply(Some(tags)),
+            Some(vec!["rust".to_string(), "warp".to_string()])
+        );
+        assert_eq!(catalog().apply(None), None);
+    }
+
+    #[test]
+    fn only_the_strict_policy_reports_unknown_tags() {
+        assert!(unknown(&["rust", "warp"], TagPolicy::Open).is_empty());
+        assert_eq!(
+            unknown(&["rust", "warp", "tokio"], TagPolicy::Strict),
+            vec![
+                "`warp` isn't in the tag catalog",
+                "`tokio` isn't in the tag catalog",
+            ]
+        );
+    }
+}
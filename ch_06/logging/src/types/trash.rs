@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    Question,
+    Answer,
+}
+
+/// Something soft-deleted, as `GET /trash` lists it. Answers deleted along
+/// with their question aren't listed by themselves, they come back with it.
+#[derive(Debug, Serialize)]
+pub struct TrashItem {
+    pub kind: TrashKind,
+    pub id: String,
+    pub question_id: String,
+    /// Title of the question, or of the question answered
+    pub title: String,
+    pub deleted_at: DateTime<Utc>,
+    /// When the background purge deletes it for good
+    pub purge_at: DateTime<Utc>,
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::types::api_key::Scope;
+use crate::types::validation::{Rule, Validate, Validator};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct UserId(pub String);
+
+/// An account as it is stored. Never sent to clients, see `UserView`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: UserId,
+    /// Normalized with `normalize_email`, unique across accounts
+    pub email: String,
+    /// Argon2 hash in PHC string format, salt and parameters included
+    pub password: String,
+    #[serde(default)]
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What an account may do besides editing and deleting what it posted.
+/// Every role may do what the ones before it may.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    /// Edits, deletes and restores anybody's questions and answers, and
+    /// curates the tag catalog
+    Moderator,
+    /// Assigns roles and sees the trash
+    Admin,
+}
+
+/// Body of `PUT /users/{id}/role`
+#[derive(Debug, Deserialize)]
+pub struct RoleBody {
+    pub role: Role,
+}
+
+/// What clients get to see of an account
+#[derive(Debug, Serialize)]
+pub struct UserView {
+    pub id: UserId,
+    pub email: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Response body of `POST /login`
+#[derive(Debug, Serialize)]
+pub struct Login {
+    /// Sent back as `Authorization: Bearer {token}`
+    pub token: String,
+    pub token_type: &'static str,
+    pub expires_at: DateTime<Utc>,
+    pub user: UserView,
+}
+
+/// Who is making a request, as its bearer token or API key says
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user_id: UserId,
+    /// Scopes of the API key the request came with, `None` for bearer
+    /// tokens, which aren't scoped
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// Body of `POST /registration` and `POST /login`
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+    pub email: String,
+    pub password: String,
+}
+
+const EMAIL_RULES: &[Rule] = &[Rule::NotBlank, Rule::MaxLength(254), Rule::Email];
+/// Argon2 takes any length, the upper bound only keeps hashing cheap
+const PASSWORD_RULES: &[Rule] = &[Rule::MinLength(8), Rule::MaxLength(128)];
+
+impl Validate for Credentials {
+    fn check(&self, validator: &mut Validator) {
+        validator
+            .text("email", &self.email, EMAIL_RULES)
+            .text("password", &self.password, PASSWORD_RULES);
+    }
+}
+
+/// Emails are compared case-insensitively, so `Jane@Example.com` can't
+/// register a second account next to `jane@example.com`
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl Session {
+    /// Whether the request may use `scope`
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.as_ref().is_none_or(|scopes| scopes.contains(&scope))
+    }
+}
+
+impl From<&User> for UserView {
+    fn from(user: &User) -> Self {
+        UserView {
+            id: user.id.clone(),
+            email: user.email.clone(),
+            role: user.role,
+            created_at: user.created_at,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
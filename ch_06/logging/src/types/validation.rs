@@ -0,0 +1,261 @@
+use handle_errors::{Error, FieldError};
+
+/// A check on one field of a payload. Length rules count characters, not
+/// bytes.
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    /// Not empty and not only whitespace
+    NotBlank,
+    MinLength(usize),
+    MaxLength(usize),
+    /// For lists: at most this many items
+    MaxItems(usize),
+    /// `local@domain`, with a dot in the domain and no whitespace
+    Email,
+    /// Only characters `allowed` accepts, which `description` names in the
+    /// error message
+    Charset {
+        allowed: fn(char) -> bool,
+        description: &'static str,
+    },
+}
+
+/// Payloads that check themselves against their rules before being stored
+pub trait Validate {
+    /// Records every rule the payload breaks in `validator`, so checks that
+    /// need more than the payload can add theirs before `finish`
+    fn check(&self, validator: &mut Validator);
+
+    /// Reports every broken rule at once, as `Error::ValidationFailed`
+    fn validate(&self) -> Result<(), Error> {
+        let mut validator = Validator::new();
+        self.check(&mut validator);
+        validator.finish()
+    }
+}
+
+/// Collects the broken rules of a payload, field by field
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    /// Checks the text `value` of `field` against `rules`
+    pub fn text(&mut self, field: &str, value: &str, rules: &[Rule]) -> &mut Self {
+        for rule in rules {
+            let broken = match *rule {
+                Rule::NotBlank if value.trim().is_empty() => {
+                    Some(("not_blank", "must not be blank".to_string()))
+                }
+                Rule::MinLength(min) if value.chars().count() < min => Some((
+                    "min_length",
+                    format!("must be at least {} characters long", min),
+                )),
+                Rule::Email if !is_email(value) => {
+                    Some(("email", "must be an email address".to_string()))
+                }
+                Rule::MaxLength(max) if value.chars().count() > max => Some((
+                    "max_length",
+                    format!("must be at most {} characters long", max),
+                )),
+                Rule::Charset {
+                    allowed,
+                    description,
+                } => value
+                    .chars()
+                    .find(|c| !allowed(*c))
+                    .map(|c| ("charset", format!("may only contain {}, not {:?}", description, c))),
+                _ => None,
+            };
+            if let Some((code, message)) = broken {
+                self.fail(field.to_string(), code, message);
+            }
+        }
+        self
+    }
+
+    /// Checks the list `items` of `field` against `rules`, and every item
+    /// against `item_rules`
+    pub fn list<T: AsRef<str>>(
+        &mut self,
+        field: &str,
+        items: &[T],
+        rules: &[Rule],
+        item_rules: &[Rule],
+    ) -> &mut Self {
+        for rule in rules {
+            if let Rule::MaxItems(max) = *rule
+                && items.len() > max
+            {
+                self.fail(
+                    field.to_string(),
+                    "max_items",
+                    format!("must have at most {} items", max),
+                );
+            }
+        }
+        for (i, item) in items.iter().enumerate() {
+            self.text(&format!("{}[{}]", field, i), item.as_ref(), item_rules);
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::ValidationFailed(std::mem::take(&mut self.errors)))
+        }
+    }
+
+    /// Records a broken rule that no `Rule` expresses, like a tag missing
+    /// from the catalog
+    pub fn fail(&mut self, field: String, code: &'static str, message: String) -> &mut Self {
+        self.errors.push(FieldError {
+            field,
+            code,
+            message,
+        });
+        self
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    match value.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| !label.is_empty())
+                && !value.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(field, code)` of every error `check` records
+    fn broken(check: impl FnOnce(&mut Validator)) -> Vec<(String, &'static str)> {
+        let mut validator = Validator::new();
+        check(&mut validator);
+        match validator.finish() {
+            Ok(()) => Vec::new(),
+            Err(Error::ValidationFailed(errors)) => {
+                errors.into_iter().map(|e| (e.field, e.code)).collect()
+            }
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    fn codes(field: &str, value: &str, rules: &[Rule]) -> Vec<&'static str> {
+        broken(|v| {
+            v.text(field, value, rules);
+        })
+        .into_iter()
+        .map(|(_, code)| code)
+        .collect()
+    }
+
+    #[test]
+    fn not_blank() {
+        assert_eq!(codes("title", "", &[Rule::NotBlank]), vec!["not_blank"]);
+        assert_eq!(codes("title", " \t\n", &[Rule::NotBlank]), vec!["not_blank"]);
+        assert!(codes("title", " a ", &[Rule::NotBlank]).is_empty());
+    }
+
+    #[test]
+    fn lengths_count_characters_not_bytes() {
+        let rules = [Rule::MinLength(3), Rule::MaxLength(5)];
+        assert_eq!(codes("t", "ab", &rules), vec!["min_length"]);
+        assert!(codes("t", "abc", &rules).is_empty());
+        assert!(codes("t", "ééééé", &rules).is_empty());
+        assert_eq!(codes("t", "abcdef", &rules), vec!["max_length"]);
+    }
+
+    #[test]
+    fn emails() {
+        for valid in ["a@b.co", "first.last@sub.example.com"] {
+            assert!(codes("email", valid, &[Rule::Email]).is_empty(), "{}", valid);
+        }
+        for invalid in ["", "a", "@b.co", "a@b", "a@b.", "a@.co", "a@b@c.co", "a b@c.co"] {
+            assert_eq!(codes("email", invalid, &[Rule::Email]), vec!["email"], "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn charset_names_the_first_bad_character() {
+        let rule = Rule::Charset {
+            allowed: |c| c.is_ascii_lowercase(),
+            description: "lowercase letters",
+        };
+        assert!(codes("tag", "rust", &[rule]).is_empty());
+
+        let mut validator = Validator::new();
+        validator.text("tag", "rUSt", &[rule]);
+        let Err(Error::ValidationFailed(errors)) = validator.finish() else {
+            panic!("expected a validation error");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "may only contain lowercase letters, not 'U'");
+    }
+
+    #[test]
+    fn lists_check_their_size_and_every_item() {
+        let items = ["ok", "", "also ok", ""];
+        let errors = broken(|v| {
+            v.list("tags", &items, &[Rule::MaxItems(3)], &[Rule::NotBlank]);
+        });
+        assert_eq!(
+            errors,
+            vec![
+                ("tags".to_string(), "max_items"),
+                ("tags[1]".to_string(), "not_blank"),
+                ("tags[3]".to_string(), "not_blank"),
+            ]
+        );
+        assert!(broken(|v| {
+            v.list::<&str>("tags", &[], &[Rule::MaxItems(3)], &[Rule::NotBlank]);
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn every_broken_rule_is_reported_in_order() {
+        let errors = broken(|v| {
+            v.text("title", "", &[Rule::NotBlank, Rule::MinLength(2)])
+                .text("content", "fine", &[Rule::NotBlank])
+                .fail("tags".to_string(), "unknown_tag", "`x` isn't known".to_string());
+        });
+        assert_eq!(
+            errors,
+            vec![
+                ("title".to_string(), "not_blank"),
+                ("title".to_string(), "min_length"),
+                ("tags".to_string(), "unknown_tag"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_finishes_what_check_records() {
+        struct Payload(&'static str);
+        impl Validate for Payload {
+            fn check(&self, validator: &mut Validator) {
+                validator.text("name", self.0, &[Rule::NotBlank]);
+            }
+        }
+        assert!(Payload("x").validate().is_ok());
+        assert!(matches!(
+            Payload("").validate(),
+            Err(Error::ValidationFailed(errors)) if errors.len() == 1
+        ));
+    }
+}
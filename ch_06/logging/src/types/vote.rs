@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::types::user::UserId;
+
+/// One user's vote on a question or an answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vote {
+    /// `{subject}/votes/{voter}`, so a user has at most one vote per subject
+    pub id: String,
+    /// `questions/{id}` or `answers/{id}`, like revisions
+    pub subject: String,
+    pub voter: UserId,
+    pub direction: Direction,
+    /// When the vote was cast or last changed
+    pub cast_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// Body of `POST /questions/{id}/votes` and `POST /answers/{id}/votes`
+/// # Example body
+/// `{"vote": "up"}`, `{"vote": "down"}`, or `{"vote": null}` to take the
+/// vote back
+#[derive(Debug, Deserialize)]
+pub struct VoteBody {
+    // Required even though it is an `Option`, so `{}` isn't read as a retraction
+    #[serde(deserialize_with = "Option::deserialize")]
+    pub vote: Option<Direction>,
+}
+
+/// Response body of the vote routes
+#[derive(Debug, Serialize)]
+pub struct VoteResult {
+    /// Score after the vote
+    pub score: i64,
+    /// The caller's vote, `None` once taken back
+    pub vote: Option<Direction>,
+}
+
+impl Vote {
+    pub fn new(subject: String, voter: UserId, direction: Direction) -> Self {
+        Vote {
+            id: Vote::id_of(&subject, &voter),
+            subject,
+            voter,
+            direction,
+            cast_at: Utc::now(),
+        }
+    }
+
+    pub fn id_of(subject: &str, voter: &UserId) -> String {
+        format!("{}/votes/{}", subject, voter)
+    }
+}
+
+impl Direction {
+    /// What the vote adds to the score
+    pub fn value(self) -> i64 {
+        match self {
+            Direction::Up => 1,
+            Direction::Down => -1,
+        }
+    }
+}